@@ -0,0 +1,63 @@
+// Syntax highlighting for --highlight-syntax, built with the "highlight" feature.
+
+use std::path::Path;
+use std::sync::OnceLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::ThemeSet;
+use syntect::parsing::SyntaxSet;
+use syntect::util::as_24_bit_terminal_escaped;
+
+// Theme used for highlighting.
+const THEME: &str = "base16-ocean.dark";
+
+// Reset all terminal colors.
+const RESET: &str = "\x1b[0m";
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn themes() -> &'static ThemeSet {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    THEMES.get_or_init(ThemeSet::load_defaults)
+}
+
+/// Highlights the lines of a single file.
+pub struct Highlighter {
+    lines: HighlightLines<'static>,
+}
+
+impl Highlighter {
+    /// Create a highlighter for the file's language, if its extension is known.
+    pub fn for_file(file: &str) -> Option<Highlighter> {
+        let extension = Path::new(file).extension()?.to_str()?;
+        let syntax = syntaxes().find_syntax_by_extension(extension)?;
+
+        Some(Highlighter {
+            lines: HighlightLines::new(syntax, &themes().themes[THEME]),
+        })
+    }
+
+    /// Color the content of a line with terminal escapes, leaving the newline uncolored so
+    /// blank lines stay blank. Lines that are not valid UTF-8 are left untouched.
+    pub fn highlight(&mut self, line: &mut Vec<u8>) {
+        let Ok(text) = std::str::from_utf8(line) else {
+            return;
+        };
+        let Ok(ranges) = self.lines.highlight_line(text, syntaxes()) else {
+            return;
+        };
+        let content = text.trim_end_matches('\n');
+
+        if content.is_empty() {
+            return;
+        }
+
+        let mut escaped = as_24_bit_terminal_escaped(&ranges, false);
+        escaped.truncate(escaped.trim_end_matches('\n').len());
+        escaped.push_str(RESET);
+        escaped.push_str(&text[content.len()..]);
+        *line = escaped.into_bytes();
+    }
+}
@@ -1,18 +1,185 @@
 // Rust implementation of the cat command.
 // Run with: cargo run -- -Asn tests/test.txt
 
+#[cfg(feature = "highlight")]
+mod highlight;
+
+use clap::error::ErrorKind;
+use clap::CommandFactory;
 use clap::Parser;
+use clap::ValueEnum;
+use flate2::write::GzEncoder;
+use regex::bytes::Regex;
+use sha2::Digest as _;
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::fs::File;
 use std::io;
 use std::io::BufRead;
 use std::io::BufReader;
+#[cfg(feature = "highlight")]
+use std::io::IsTerminal;
+use std::io::Read;
 use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::ExitCode;
+use std::process::Stdio;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 
 // Constant for stdin file name.
 const FILENAME_STDIN: &str = "-";
 
+// Distance between tab stops for --show-tabs-aligned.
+const TAB_WIDTH: usize = 8;
+
+// How the lines of the input are processed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    // Copy lines unchanged.
+    Copy,
+    // Only drop repeated blank lines (-s alone).
+    Squeeze,
+    // Run every line through format_line.
+    Format,
+}
+
+/// Where the --crc-lines checksum is placed on each line.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum CrcPosition {
+    Prefix,
+    Suffix,
+}
+
+/// Padding used to right-justify line numbers.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum NumberPad {
+    Space,
+    Zero,
+}
+
+/// Bundles of options for common viewing tasks.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Profile {
+    /// equivalent to -s
+    Log,
+    /// equivalent to -nT
+    Code,
+}
+
+/// Layout of the --byte-histogram report.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Histogram {
+    /// one line per byte value that occurs
+    Table,
+    /// counts of printable, control and non-ASCII bytes
+    Summary,
+}
+
+/// What to do with input files that do not exist.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum OnMissing {
+    /// continue silently without output
+    Skip,
+    /// report the file and exit with status 1
+    Error,
+    /// read the file as if it were empty, without a warning
+    Empty,
+}
+
+/// Lines numbered by --number-style, as in nl -b.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum NumberStyle {
+    /// number all lines (nl -ba)
+    All,
+    /// number nonblank lines (nl -bt)
+    Nonblank,
+    /// number no lines, only indent them (nl -bn)
+    #[value(name = "none")]
+    Off,
+}
+
+/// How much of the input is read ahead.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum InputBuffering {
+    /// read in large blocks
+    Full,
+    /// read in small blocks
+    Line,
+    /// read a byte at a time, never consuming more input than is processed
+    None,
+}
+
+/// Which lines count as blank, for --blank-lines-are.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum BlankLines {
+    /// only empty lines
+    Empty,
+    /// lines of spaces and tabs only
+    Whitespace,
+    /// empty lines, with LF or CRLF endings
+    EmptyOrCrlf,
+    /// empty lines and lines holding only a form feed
+    EmptyOrFormfeed,
+}
+
+/// Which blank line of a run -s keeps.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum SqueezeKeep {
+    First,
+    Last,
+}
+
+/// How --invalid shows bytes that are not valid UTF-8.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Invalid {
+    /// replace each invalid sequence with U+FFFD
+    Replace,
+    /// escape each invalid byte in M- notation, as -v does
+    Escape,
+    /// show each invalid byte as <0xNN>
+    Marker,
+}
+
+/// Comment syntaxes removed by --strip-comments.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum CommentStyle {
+    /// # comments (shell, Python, TOML)
+    Hash,
+    /// // comments (C, Rust, JavaScript)
+    Slash,
+    /// ; comments (INI, assembly, Lisp)
+    Semicolon,
+}
+
+/// Newline handling of the output, for --output-mode.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum OutputMode {
+    /// write bytes unchanged
+    Binary,
+    /// write each LF as CRLF, as a Windows text-mode stream does, leaving CRLF alone
+    Text,
+}
+
+impl CommentStyle {
+    fn marker(self) -> &'static [u8] {
+        match self {
+            CommentStyle::Hash => b"#",
+            CommentStyle::Slash => b"//",
+            CommentStyle::Semicolon => b";",
+        }
+    }
+}
+
 /// Argument parser
-#[derive(Parser)]
+#[derive(Clone, Debug, Parser)]
 #[clap(about = "Concatenate FILE(s) to standard output.\n\nWith no FILE, or when FILE is -, read \
                 standard input.",
        after_help = "Examples:\n   cat f - g  Output f's contents, then standard input, then g's \
@@ -34,67 +201,511 @@ struct Args {
     /// number all output lines
     #[arg(long, short = 'n')]
     number: bool,
+    /// number lines as ORIG:N, ORIG being the position of the line in the input and N counting
+    /// only the lines printed after --sample, --since, --until and --unique-field
+    #[arg(long, conflicts_with_all = ["number_nonblank", "number_style", "number_format"])]
+    dual_number: bool,
+    /// number lines starting from N
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        allow_negative_numbers = true
+    )]
+    start_number: i64,
+    /// right-justify line numbers with spaces or zeros (default width: 6)
+    #[arg(long, value_enum)]
+    number_pad: Option<NumberPad>,
+    /// right-justify line numbers to N columns
+    #[arg(long, value_name = "N")]
+    number_width: Option<usize>,
+    /// number lines in the layout of nl: all, nonblank or none
+    #[arg(long, value_name = "STYLE", value_enum,
+          conflicts_with_all = ["number_pad", "number_width", "number_format"])]
+    number_style: Option<NumberStyle>,
+    /// render line numbers with FORMAT, a printf string with one %d (flags - and 0, width)
+    #[arg(long, value_name = "FORMAT", value_parser = parse_number_format,
+          conflicts_with_all = ["number_pad", "number_width"])]
+    number_format: Option<NumberFormat>,
     /// suppress repeated empty output lines
     #[arg(long, short = 's')]
     squeeze_blank: bool,
     /// equivalent to -vT
     #[arg(short = 't')]
     t: bool,
+    /// display TAB characters as ^I padded to the next tab stop
+    #[arg(long)]
+    show_tabs_aligned: bool,
+    /// highlight source code by file extension when writing to a terminal (needs the
+    /// "highlight" feature; ignored with -v)
+    #[arg(long, conflicts_with = "truncate_with_number")]
+    highlight_syntax: bool,
+    /// display CR as ^M and LF as $ to reveal line endings
+    #[arg(long)]
+    show_line_endings: bool,
     /// display TAB characters as ^I
     #[arg(long, short = 'T')]
     show_tabs: bool,
+    /// display STR at the end of each line with -E instead of $
+    #[arg(
+        long,
+        value_name = "STR",
+        default_value = "$",
+        hide_default_value = true
+    )]
+    end_marker: String,
+    /// display TAB characters as STR instead of ^I
+    #[arg(long, value_name = "STR", default_value = "^I", hide_default_value = true,
+          value_parser = clap::builder::NonEmptyStringValueParser::new(), alias = "tab-char")]
+    tab_marker: String,
     /// (ignored)
     #[arg(short = 'u')]
     u: bool,
+    /// read-ahead of the input: full, line or none
+    #[arg(long, value_name = "MODE", value_enum, default_value_t = InputBuffering::Full)]
+    input_buffering: InputBuffering,
     /// use ^ and M- notation, except for LFD and TAB
     #[arg(long, short = 'v')]
     show_non_printing: bool,
+    /// make invalid UTF-8 visible: replace, escape or marker
+    #[arg(long, value_name = "HOW", value_enum)]
+    invalid: Option<Invalid>,
+    /// show control characters as Unicode Control Pictures (U+2400..U+2421) instead of ^ notation
+    #[arg(long)]
+    control_pictures: bool,
+    /// wrap -v output at WIDTH columns (default: terminal width) without splitting escapes
+    #[arg(long, value_name = "WIDTH", num_args = 0..=1, require_equals = true,
+          default_missing_value = "0")]
+    wrap_nonprinting: Option<usize>,
+    /// cut lines longer than WIDTH columns, ending them with --truncate-marker (WIDTH must fit
+    /// the marker)
+    #[arg(long, value_name = "WIDTH", conflicts_with = "wrap_nonprinting")]
+    truncate: Option<usize>,
+    /// text ending truncated lines (default: …)
+    #[arg(
+        long,
+        value_name = "STR",
+        default_value = "…",
+        hide_default_value = true,
+        requires = "truncate"
+    )]
+    truncate_marker: String,
+    /// count the line number toward the --truncate width
+    #[arg(long, requires = "truncate")]
+    truncate_with_number: bool,
+    /// remove the leading whitespace common to all nonblank lines of each file
+    #[arg(long)]
+    dedent: bool,
+    /// join the lines of each paragraph and wrap them at WIDTH columns
+    #[arg(long, value_name = "WIDTH",
+          value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    reflow: Option<usize>,
+    /// transcode UTF-16 input with a byte order mark to UTF-8 and drop UTF-8 byte order marks
+    #[arg(long)]
+    detect_encoding: bool,
+    /// keep the first or the last blank line of each squeezed run
+    #[arg(long, value_name = "WHICH", value_enum, default_value_t = SqueezeKeep::First,
+          conflicts_with = "trim_blank_runs_to")]
+    squeeze_keep: SqueezeKeep,
+    /// squeeze blank runs to one line, printed only if a nonblank line follows it
+    #[arg(long, conflicts_with = "trim_blank_runs_to")]
+    preserve_blank_before_nonblank_only: bool,
+    /// only squeeze files larger than N bytes
+    #[arg(long, value_name = "N")]
+    squeeze_min_bytes: Option<u64>,
+    /// only squeeze some of the files: all, first, last or positions such as 1,3
+    #[arg(long, value_name = "WHICH", value_parser = parse_squeeze_files)]
+    squeeze_files: Option<SqueezeFiles>,
+    /// suppress repeated whitespace-only lines, printing one empty line per run
+    #[arg(long)]
+    squeeze_whitespace_lines: bool,
+    /// print the file name before each line
+    #[arg(long, short = 'H', overrides_with = "no_filename")]
+    with_filename: bool,
+    /// do not print file names, overriding --with-filename
+    #[arg(long, overrides_with = "with_filename")]
+    no_filename: bool,
+    /// name standard input NAME in file name prefixes
+    #[arg(long, value_name = "NAME")]
+    stdin_name: Option<String>,
+    /// print STR before each line (supports \t and \n escapes)
+    #[arg(long, value_name = "STR")]
+    line_prefix: Option<String>,
+    /// print STR at the end of each line (supports \t and \n escapes)
+    #[arg(long, value_name = "STR")]
+    line_suffix: Option<String>,
+    /// print only every Nth input line
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    sample: Option<u64>,
+    /// first input line printed by --sample (default: N)
+    #[arg(long, value_name = "N", requires = "sample",
+          value_parser = clap::value_parser!(u64).range(1..))]
+    sample_offset: Option<u64>,
+    /// print STR on its own line between runs of adjacent lines kept by --sample
+    #[arg(long, value_name = "STR", requires = "sample")]
+    group_separator: Option<String>,
+    /// print only lines whose leading timestamp is at or after TIME
+    #[arg(long, value_name = "TIME")]
+    since: Option<String>,
+    /// print only lines whose leading timestamp is at or before TIME
+    #[arg(long, value_name = "TIME")]
+    until: Option<String>,
+    /// layout of the timestamps for --since and --until (%Y %m %d %H %M %S and %%)
+    #[arg(long, value_name = "FORMAT", default_value = "%Y-%m-%d %H:%M:%S")]
+    time_format: String,
+    // Parsed --since and --until.
+    #[arg(skip)]
+    time_window: (Option<Timestamp>, Option<Timestamp>),
+    /// print only the first N bytes of the input, without formatting
+    #[arg(long, value_name = "N", conflicts_with = "tail_bytes")]
+    head_bytes: Option<u64>,
+    /// print only the last N bytes of the input, without formatting
+    #[arg(long, value_name = "N")]
+    tail_bytes: Option<u64>,
+    /// print how often each byte value occurs instead of the content
+    #[arg(long, value_enum, value_name = "LAYOUT", num_args = 0..=1, require_equals = true,
+          default_missing_value = "table", conflicts_with_all = ["head_bytes", "tail_bytes"])]
+    byte_histogram: Option<Histogram>,
+    /// report the offset of each invalid UTF-8 sequence instead of the content
+    #[arg(long, conflicts_with_all = ["byte_histogram", "head_bytes", "tail_bytes"])]
+    validate_utf8: bool,
+    /// print only the lines that differ from the line at the same position of PATH, marking
+    /// baseline lines with - and input lines with +
+    #[arg(long, value_name = "PATH",
+          conflicts_with_all = ["byte_histogram", "validate_utf8", "head_bytes", "tail_bytes"])]
+    diff_base: Option<String>,
+    /// collapse runs of spaces within each line to a single space
+    #[arg(long)]
+    squeeze_spaces: bool,
+    /// byte squeezed by --squeeze-spaces (supports \t)
+    #[arg(long, value_name = "CHAR", default_value = " ", hide_default_value = true,
+          value_parser = parse_byte, requires = "squeeze_spaces")]
+    squeeze_char: u8,
+    /// collapse runs of tabs within each line to a single tab
+    #[arg(long)]
+    squeeze_tabs: bool,
+    /// remove comments of the given STYLE, dropping comment-only lines; markers inside
+    /// strings are not recognized and are stripped too
+    #[arg(long, value_name = "STYLE", value_enum)]
+    strip_comments: Option<CommentStyle>,
+    /// keep at most N blank lines where a blank run meets a file boundary
+    #[arg(long, value_name = "N")]
+    trim_blank_runs_to: Option<usize>,
+    /// which lines count as blank for -s, -b and blank-run trimming
+    #[arg(long, value_name = "KIND", value_enum)]
+    blank_lines_are: Option<BlankLines>,
+    /// treat lines holding only a form feed as blank
+    #[arg(long)]
+    formfeed_blank: bool,
+    /// treat CRLF-only lines as blank
+    #[arg(long)]
+    normalize_blanks: bool,
+    /// tag each line as [blank], [comment], [text] or [binary], before any line number
+    #[arg(long)]
+    classify: bool,
+    /// annotate each line with the CRC32 of its raw content
+    #[arg(long)]
+    crc_lines: bool,
+    /// where to place the --crc-lines checksum
+    #[arg(long, value_enum, default_value_t = CrcPosition::Prefix)]
+    crc_position: CrcPosition,
+    /// replace ${VAR} with the value of environment variable VAR and $$ with $
+    #[arg(long)]
+    expand_env: bool,
+    /// fail on unset variables with --expand-env
+    #[arg(long, requires = "expand_env")]
+    strict_env: bool,
+    /// replace occurrences of FROM with TO on each line (repeatable, applied in order)
+    #[arg(long, num_args = 2, value_names = ["FROM", "TO"])]
+    replace: Vec<String>,
+    /// treat each --replace FROM as a regular expression
+    #[arg(long)]
+    replace_regex: bool,
+    // Compiled --replace patterns with their replacements.
+    #[arg(skip)]
+    replacements: Vec<(Regex, String)>,
+    /// map each byte in the set FROM to the byte at the same place in TO, like tr (repeatable)
+    #[arg(long, num_args = 2, value_names = ["FROM", "TO"])]
+    translate: Vec<String>,
+    /// map every output byte through the 256-byte table in PATH
+    #[arg(long, value_name = "PATH", conflicts_with = "translate")]
+    map_file: Option<String>,
+    // Byte mapping built from --translate or --map-file.
+    #[arg(skip)]
+    byte_map: Option<Box<[u8; 256]>>,
+    /// convert ASCII letters to upper case
+    #[arg(long, conflicts_with_all = ["lower", "invert_case"])]
+    upper: bool,
+    /// convert ASCII letters to lower case
+    #[arg(long, conflicts_with = "invert_case")]
+    lower: bool,
+    /// swap the case of ASCII letters
+    #[arg(long)]
+    invert_case: bool,
+    /// reverse the bytes of each line
+    #[arg(long)]
+    reverse_bytes: bool,
+    /// reverse the UTF-8 characters of each line
+    #[arg(long, conflicts_with = "reverse_bytes")]
+    reverse_chars: bool,
+    /// enable a predefined bundle of options
+    #[arg(long, value_enum)]
+    profile: Option<Profile>,
+    /// print diagnostic messages to stderr
+    #[arg(long)]
+    verbose: bool,
+    /// warn about inputs that do not end with a newline
+    #[arg(long)]
+    warn_no_newline: bool,
+    /// copy the input byte for byte; options that change the output are an error
+    #[arg(long)]
+    raw: bool,
+    /// read each file completely before writing it (stdin is still streamed)
+    #[arg(long)]
+    read_whole: bool,
+    /// give up on an input when no data arrives for MS milliseconds (Unix only)
+    #[arg(long, value_name = "MS")]
+    read_timeout: Option<u64>,
+    /// give up on standard input when it is a terminal and nothing arrives within MS
+    /// milliseconds (Unix only)
+    #[arg(long, value_name = "MS")]
+    stdin_timeout: Option<u64>,
+    /// print a partial line when no newline arrives for MS milliseconds (Unix only)
+    #[arg(long, value_name = "MS", conflicts_with_all = ["read_timeout", "raw", "crc_lines",
+          "line_suffix", "truncate", "sample", "since", "until", "split_marker",
+          "unique_field"])]
+    partial_line_timeout: Option<u64>,
+    /// compress the output with gzip or zstd
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    compress: Option<Compression>,
+    /// write at most BYTES bytes per second (K, M and G suffixes allowed)
+    #[arg(long, value_name = "BYTES", value_parser = parse_rate)]
+    rate_limit: Option<u64>,
+    /// binary writes newlines as they are, text writes each bare LF as CRLF
+    #[arg(long, value_name = "MODE", value_enum, default_value_t = OutputMode::Binary)]
+    output_mode: OutputMode,
+    /// write the output of the Nth input to PREFIXN instead of standard output
+    #[arg(long, value_name = "PREFIX", conflicts_with = "compress")]
+    split: Option<String>,
+    /// start a new output file at each line matching REGEX, named by its first capture group;
+    /// the file must not exist yet and is created in the current directory
+    #[arg(long, value_name = "REGEX", value_parser = parse_split_marker)]
+    split_marker: Option<Regex>,
+    /// collapse runs of consecutive lines whose REGEX match (or first capture group) is the
+    /// same, keeping the first line of each run
+    #[arg(long, value_name = "REGEX")]
+    unique_field: Option<Regex>,
+    /// restart line numbers with each input
+    #[arg(long)]
+    number_per_file: bool,
+    /// compare the output against the system cat and report differences
+    #[arg(long, hide = true)]
+    compat_check: bool,
+    /// print the options in effect after alias expansion to standard error
+    #[arg(long, hide = true)]
+    explain: bool,
+    /// how to handle missing input files
+    #[arg(long, value_name = "POLICY", value_enum, default_value_t = OnMissing::Error)]
+    on_missing: OnMissing,
+    /// keep reading a non-blocking input that is momentarily empty until its writer closes it
+    #[arg(long)]
+    fifo_drain: bool,
+    /// report unreadable files but always exit with status 0
+    #[arg(long)]
+    ignore_errors: bool,
+    /// print the contents of PATH before the inputs, outside line numbering
+    #[arg(long, value_name = "PATH")]
+    prepend: Option<String>,
+    /// print the contents of PATH after the inputs, outside line numbering
+    #[arg(long, value_name = "PATH")]
+    append_file: Option<String>,
+    /// format the --prepend and --append-file contents like the other inputs
+    #[arg(long)]
+    wrap_inside: bool,
+    /// report the bytes, lines, files read and failed, and blank lines squeezed to standard
+    /// error at the end
+    #[arg(long)]
+    summary: bool,
+    /// end the output with a newline if it does not already end with one
+    #[arg(long)]
+    add_final_newline: bool,
+    /// start each file on a new line, even if the previous one lacks a trailing newline
+    #[arg(long)]
+    ensure_newline_between_files: bool,
+    /// verify the input against the digest in FILE ("sha256:HEX", "crc32:HEX" or bare SHA-256)
+    #[arg(long, value_name = "FILE")]
+    hash_check: Option<String>,
+    /// do not write the content, only check it
+    #[arg(long, short = 'q', requires = "hash_check")]
+    quiet: bool,
+    /// report every printed line wider than N columns and exit with status 1
+    #[arg(long, value_name = "N")]
+    assert_max_width: Option<usize>,
+    /// add N to the scheduling niceness before reading (Unix only; ignored elsewhere)
+    #[arg(long, value_name = "N", allow_negative_numbers = true)]
+    nice: Option<i32>,
+    /// stop with exit status 2 if an input starts with binary data (a NUL byte)
+    #[arg(long)]
+    fail_on_binary: bool,
+    /// buffer the output, flushing it after a line once MS milliseconds have passed, or when
+    /// the input has been idle for MS milliseconds
+    #[arg(long, value_name = "MS")]
+    flush_interval: Option<u64>,
+    /// process at most N input files, warning about the rest
+    #[arg(long, value_name = "N")]
+    limit_files: Option<usize>,
     // Inpute files (default to stdin if none is provided)
     #[arg(default_values_t = [FILENAME_STDIN.to_string()], hide_default_value = true)]
     files: Vec<String>,
 }
 
-// Add formatting to the buffer based on the input arguments.
-fn format_buffer(line: &mut Vec<u8>, args: &Args, line_number: &mut i32, newlines: &mut i32) {
-    let is_new_line = line.len() == 1 && line[0] == 10;
-    let new_line_idx = line.iter().position(|&x| x == 10);
+// Whether the line counts as blank for squeezing, -b numbering and blank-run trimming.
+fn is_blank_line(line: &[u8], args: &Args) -> bool {
+    let Some(content) = line.strip_suffix(b"\n") else {
+        return false;
+    };
+
+    let kind = args.blank_lines_are;
+
+    content.is_empty()
+        || (args.formfeed_blank || kind == Some(BlankLines::EmptyOrFormfeed)) && content == b"\x0c"
+        || (args.normalize_blanks || kind == Some(BlankLines::EmptyOrCrlf)) && content == b"\r"
+        || (args.squeeze_whitespace_lines || kind == Some(BlankLines::Whitespace))
+            && content.iter().all(|&c| c == b' ' || c == b'\t')
+}
+
+// Colors the content of a line for --highlight-syntax.
+type Highlight<'a> = &'a mut dyn FnMut(&mut Vec<u8>);
+
+// Add formatting to the line based on the input arguments. The line number, the count of
+// consecutive blank lines, the position of the line in the input (for --dual-number) and the
+// name of its file (for -H) are passed in, so the function works on a line in isolation.
+fn format_line(
+    line: &mut Vec<u8>,
+    args: &Args,
+    line_number: &mut i64,
+    newlines: &mut u64,
+    original: u64,
+    file_name: Option<&str>,
+    highlight: Option<Highlight>,
+) -> io::Result<()> {
+    // Classify the line as read, before anything changes it.
+    let tag = args.classify.then(|| classify(line, args));
+
+    // Checksum the raw content before any transformation.
+    let crc = args
+        .crc_lines
+        .then(|| format!("{:08x}", crc32fast::hash(&line[..content_len(line)])));
+
+    // Strip comments, naively taking the first marker on the line.
+    if let Some(style) = args.strip_comments {
+        let marker = style.marker();
+        // Keep the CR of a CRLF ending.
+        let len = content_len(line);
+        let len = len - usize::from(line[..len].ends_with(b"\r"));
+
+        if let Some(idx) = line[..len]
+            .windows(marker.len())
+            .position(|window| window == marker)
+        {
+            let code_len = line[..idx].trim_ascii_end().len();
+            if code_len == 0 {
+                // Comment-only lines are dropped entirely and take no line number.
+                line.clear();
+                return Ok(());
+            }
+            line.drain(code_len..len);
+        }
+    }
+
+    // Collapse whitespace-only lines to empty lines.
+    if args.squeeze_whitespace_lines
+        && line.ends_with(b"\n")
+        && line[..line.len() - 1]
+            .iter()
+            .all(|&c| c == b' ' || c == b'\t')
+    {
+        *line = b"\n".to_vec();
+    }
 
-    if is_new_line && args.squeeze_blank {
-        *newlines += 1;
+    let is_new_line = is_blank_line(line, args);
+
+    if is_new_line && (args.squeeze_blank || args.squeeze_whitespace_lines) {
+        // Saturate, so no blank run is long enough to wrap the counter around.
+        *newlines = newlines.saturating_add(1);
 
         if *newlines > 1 {
             line.clear();
-            return;
+            return Ok(());
         }
     } else {
         // Not an empty line.
         *newlines = 0;
     }
 
-    // Show ends.
-    if args.show_ends {
-        line.insert(new_line_idx.unwrap(), b'$');
+    // Expand environment variables.
+    if args.expand_env {
+        *line = expand_env(line, args.strict_env)?;
     }
 
-    // Show non-printing.
-    if args.show_non_printing {
+    // Collapse runs of the squeezed byte.
+    if args.squeeze_spaces {
+        line.dedup_by(|a, b| *a == args.squeeze_char && *b == args.squeeze_char);
+    }
+    if args.squeeze_tabs {
+        line.dedup_by(|a, b| *a == b'\t' && *b == b'\t');
+    }
+
+    // Apply the replacements in order.
+    if !args.replacements.is_empty() {
+        let content_len = content_len(line);
+        let mut content = line[..content_len].to_vec();
+
+        for (pattern, replacement) in &args.replacements {
+            content = pattern
+                .replace_all(&content, replacement.as_bytes())
+                .into_owned();
+        }
+
+        line.splice(..content_len, content);
+    }
+
+    // Change letter case.
+    if args.upper {
+        line.make_ascii_uppercase();
+    } else if args.lower {
+        line.make_ascii_lowercase();
+    } else if args.invert_case {
+        for c in line.iter_mut() {
+            if c.is_ascii_alphabetic() {
+                *c ^= 0x20;
+            }
+        }
+    }
+
+    // Reverse the line content, keeping the newline at the end.
+    if args.reverse_bytes || args.reverse_chars {
+        let content_len = content_len(line);
+
+        if args.reverse_bytes {
+            line[..content_len].reverse();
+        } else {
+            reverse_chars(&mut line[..content_len]);
+        }
+    }
+
+    // Flag invalid UTF-8.
+    if let Some(invalid) = args.invalid {
+        *line = show_invalid_utf8(line, invalid);
+    }
+
+    // Show carriage returns.
+    if args.show_line_endings {
         *line = line
             .iter()
             .flat_map(|c| {
-                if *c < 32 && *c != b'\n' && *c != b'\t' {
-                    vec![b'^', *c + 64]
-                } else if *c == 127 {
-                    vec![b'^', b'?']
-                } else if *c > 127 {
-                    if *c >= 128 + 32 {
-                        if *c < 255 {
-                            vec![b'M', b'-', *c - 128]
-                        } else {
-                            vec![b'M', b'-', b'^', b'?']
-                        }
-                    } else {
-                        vec![b'M', b'-', b'^', *c - 128 + 64]
-                    }
+                if *c == b'\r' {
+                    vec![b'^', b'M']
                 } else {
                     vec![*c]
                 }
@@ -102,13 +713,22 @@ fn format_buffer(line: &mut Vec<u8>, args: &Args, line_number: &mut i32, newline
             .collect();
     }
 
+    // Show non-printing.
+    if args.show_non_printing {
+        *line = line.iter().flat_map(|c| non_printing(*c)).collect();
+    } else if args.control_pictures {
+        *line = line.iter().flat_map(|c| control_picture(*c)).collect();
+    }
+
     // Show tabs.
-    if args.show_tabs {
+    if args.show_tabs_aligned {
+        show_tabs_aligned(line, &args.tab_marker);
+    } else if args.show_tabs {
         *line = line
             .iter()
             .flat_map(|c| {
                 if *c == b'\t' {
-                    vec![b'^', b'I']
+                    args.tab_marker.as_bytes().to_vec()
                 } else {
                     vec![*c]
                 }
@@ -116,178 +736,5555 @@ fn format_buffer(line: &mut Vec<u8>, args: &Args, line_number: &mut i32, newline
             .collect();
     }
 
-    // Add line numbers.
-    if args.number || args.number_nonblank && !is_new_line {
-        line.splice(0..0, line_number.to_string().bytes().chain(vec![b' ']));
+    // Show ends, after the escapes so they leave the marker alone.
+    if args.show_ends || args.show_line_endings {
+        let idx = content_len(line);
+
+        if idx < line.len() {
+            // Like GNU cat, also reveal the CR of a CRLF ending.
+            if args.show_ends && idx > 0 && line[idx - 1] == b'\r' {
+                line.splice(
+                    idx - 1..idx,
+                    b"^M".iter().chain(args.end_marker.as_bytes()).copied(),
+                );
+            } else {
+                line.splice(idx..idx, args.end_marker.bytes());
+            }
+        }
+    }
+
+    // Wrap the escaped output.
+    if let Some(width) = args.wrap_nonprinting.filter(|_| args.show_non_printing) {
+        wrap_escaped(line, width);
+    }
+
+    // Truncate the content.
+    if let Some(width) = args.truncate.filter(|_| !args.truncate_with_number) {
+        truncate_line(line, width, &args.truncate_marker);
+    }
+
+    // Color the finished content, so no other step sees the escapes.
+    if let Some(highlight) = highlight {
+        highlight(line);
+    }
+
+    // Add the line checksum.
+    if let Some(crc) = crc {
+        match args.crc_position {
+            CrcPosition::Prefix => {
+                line.splice(0..0, crc.bytes().chain([b' ']));
+            }
+            CrcPosition::Suffix => {
+                let idx = content_len(line);
+                line.splice(idx..idx, [b' '].into_iter().chain(crc.bytes()));
+            }
+        }
+    }
+
+    // Number the line. As in GNU cat, -n numbers every printed line and -b only the nonblank
+    // ones (main clears -n when -b is set). Lines dropped by -s returned above, so they never
+    // take a number.
+    let mut label = if args.number || (args.number_nonblank && !is_new_line) {
+        let label = match &args.number_format {
+            _ if args.dual_number => format!("{}:{} ", original, line_number),
+            Some(format) => format.render(*line_number),
+            None if args.number_style.is_some() => format!("{:>6}\t", line_number),
+            None => format_number(args, *line_number) + " ",
+        };
         *line_number += 1;
+        label
+    } else if args.number_style.is_some() {
+        // Like nl, keep unnumbered lines aligned with the numbered ones.
+        String::from("       ")
+    } else {
+        String::new()
+    };
+
+    if let Some(tag) = tag {
+        label.insert_str(0, &format!("[{}] ", tag));
+    }
+
+    assemble_line(line, args, file_name, label.as_bytes());
+
+    // Map every byte of the output.
+    if let Some(byte_map) = &args.byte_map {
+        for c in line.iter_mut() {
+            *c = byte_map[usize::from(*c)];
+        }
     }
+
+    Ok(())
 }
 
-// Cat: read from input and print to stdout adding formatting if needed.
-fn cat(args: &Args, file: &String, needs_formatting: bool, line_number: &mut i32) {
-    let mut reader: Box<dyn BufRead> = if file == FILENAME_STDIN {
-        // Read from stdin.
-        Box::new(BufReader::new(io::stdin()))
+// Put the output line together, always in this order: the -H file name, --line-prefix, the
+// label (--classify tag and line number), the content (with the -E `$`), --line-suffix, then
+// the line ending.
+fn assemble_line(line: &mut Vec<u8>, args: &Args, file_name: Option<&str>, label: &[u8]) {
+    let mut numbered = Vec::with_capacity(label.len() + line.len());
+    numbered.extend_from_slice(label);
+    numbered.append(line);
+
+    // Truncate the content and the line number together.
+    if let Some(width) = args.truncate.filter(|_| args.truncate_with_number) {
+        truncate_line(&mut numbered, width, &args.truncate_marker);
+    }
+
+    let end = content_len(&numbered);
+    let prefix = args.line_prefix.as_deref().unwrap_or_default();
+    let suffix = args.line_suffix.as_deref().unwrap_or_default();
+
+    line.reserve(prefix.len() + numbered.len() + suffix.len());
+    if let Some(file_name) = file_name {
+        line.extend_from_slice(file_name.as_bytes());
+        line.push(b':');
+    }
+    line.extend_from_slice(prefix.as_bytes());
+    line.extend_from_slice(&numbered[..end]);
+    line.extend_from_slice(suffix.as_bytes());
+    line.extend_from_slice(&numbered[end..]);
+}
+
+// Tag a line for --classify. Binary lines hold NUL or other control bytes, or invalid UTF-8;
+// comments start with #, // or ; after any indentation.
+fn classify(line: &[u8], args: &Args) -> &'static str {
+    let content = &line[..content_len(line)];
+
+    if is_blank_line(line, args) {
+        "blank"
+    } else if content
+        .utf8_chunks()
+        .any(|chunk| !chunk.invalid().is_empty())
+        || content
+            .iter()
+            .any(|&c| c.is_ascii_control() && !matches!(c, b'\t' | b'\r' | b'\x0c'))
+    {
+        "binary"
+    } else if [&b"#"[..], b"//", b";"]
+        .iter()
+        .any(|marker| content.trim_ascii_start().starts_with(marker))
+    {
+        "comment"
     } else {
-        Box::new(BufReader::new(File::open(file).unwrap()))
-    };
-    let mut line: Vec<u8> = Vec::new();
-    let mut newlines: i32 = 0;
+        "text"
+    }
+}
 
-    // Iterate over the reader line by line.
-    loop {
-        match reader.read_until(b'\n', &mut line) {
-            Ok(bytes_read) if bytes_read > 0 => {
-                if !needs_formatting {
-                    // Print the buffer to stdout as is.
-                    io::stdout().write_all(line.as_slice()).unwrap();
-                } else {
-                    format_buffer(&mut line, args, line_number, &mut newlines);
-                    io::stdout().write_all(line.as_slice()).unwrap();
-                }
+// Expand a tr-style set: escapes as in unescape, and ranges such as a-z.
+fn expand_set(spec: &str) -> Result<Vec<u8>, String> {
+    let bytes = unescape(spec).into_bytes();
+    let mut set = Vec::new();
+    let mut idx = 0;
 
-                line.clear();
+    while idx < bytes.len() {
+        match bytes[idx..] {
+            [first, b'-', last, ..] => {
+                if first > last {
+                    return Err(format!(
+                        "range {}-{} is in reverse order",
+                        first as char, last as char
+                    ));
+                }
+                set.extend(first..=last);
+                idx += 3;
             }
-            Ok(_) => break, // EOF.
-            Err(e) => {
-                eprintln!("Error reading line: {}", e);
-                break;
+            _ => {
+                set.push(bytes[idx]);
+                idx += 1;
             }
         }
     }
+
+    Ok(set)
 }
 
-fn main() {
-    let mut args = Args::parse();
+// Build the --translate mapping. As in tr, a shorter TO repeats its last byte.
+fn translation_table(from: &str, to: &str) -> Result<[u8; 256], String> {
+    let from = expand_set(from)?;
+    let to = expand_set(to)?;
+    let Some(&last) = to.last() else {
+        return Err(String::from("TO must not be empty"));
+    };
 
-    // Set aliases and overrides.
-    if args.e {
-        args.show_ends = true;
-        args.show_non_printing = true;
+    let mut table: [u8; 256] = std::array::from_fn(|c| c as u8);
+    for (idx, &c) in from.iter().enumerate() {
+        table[usize::from(c)] = to.get(idx).copied().unwrap_or(last);
     }
+    Ok(table)
+}
 
-    if args.number_nonblank {
-        args.number = false;
+// Year, month, day, hour, minute and second, compared field by field. Fields missing from
+// --time-format are zero.
+type Timestamp = [u32; 6];
+
+// Parse the timestamp at the start of the text with a small strftime subset.
+fn parse_timestamp(format: &str, text: &[u8]) -> Option<Timestamp> {
+    let mut timestamp = [0; 6];
+    let mut rest = text;
+    let mut chars = format.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            let mut buf = [0; 4];
+            rest = rest.strip_prefix(c.encode_utf8(&mut buf).as_bytes())?;
+            continue;
+        }
+
+        let (field, digits) = match chars.next()? {
+            'Y' => (0, 4),
+            'm' => (1, 2),
+            'd' => (2, 2),
+            'H' => (3, 2),
+            'M' => (4, 2),
+            'S' => (5, 2),
+            '%' => {
+                rest = rest.strip_prefix(b"%")?;
+                continue;
+            }
+            _ => return None,
+        };
+
+        let value = rest.get(..digits)?;
+        if !value.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        timestamp[field] = value
+            .iter()
+            .fold(0, |acc, c| acc * 10 + u32::from(c - b'0'));
+        rest = &rest[digits..];
     }
 
-    if args.show_all {
-        args.show_ends = true;
-        args.show_non_printing = true;
-        args.show_tabs = true;
+    Some(timestamp)
+}
+
+// Whether the line falls inside the --since/--until window. Lines without a timestamp share
+// the fate of the line before them, so multiline records stay whole; lines before the first
+// timestamp are left out.
+fn in_time_window(args: &Args, state: &mut State, line: &[u8]) -> bool {
+    let (since, until) = args.time_window;
+    if since.is_none() && until.is_none() {
+        return true;
     }
 
-    if args.t {
-        args.show_non_printing = true;
-        args.show_tabs = true;
+    if let Some(timestamp) = parse_timestamp(&args.time_format, line) {
+        state.in_window = since.is_none_or(|since| timestamp >= since)
+            && until.is_none_or(|until| timestamp <= until);
     }
+    state.in_window
+}
 
-    // Check if the input needs to be manipulated before printing.
-    let needs_formatting = args.number
-        || args.number_nonblank
-        || args.show_ends
-        || args.squeeze_blank
-        || args.show_tabs
-        || args.show_non_printing;
+// Whether the line repeats the --unique-field value of the line before it. A line the pattern
+// does not match is kept and ends the run.
+fn repeats_field(args: &Args, state: &mut State, line: &[u8]) -> bool {
+    let Some(pattern) = &args.unique_field else {
+        return false;
+    };
 
-    // Line number, increases across files.
-    let mut line_number: i32 = 1;
+    let field = pattern
+        .captures(&line[..content_len(line)])
+        .and_then(|captures| captures.get(1).or_else(|| captures.get(0)))
+        .map(|field| field.as_bytes().to_vec());
+    let repeated = field.is_some() && field == state.last_field;
+    state.last_field = field;
+    repeated
+}
 
-    for file in &args.files {
-        cat(&args, file, needs_formatting, &mut line_number);
+// Compile a --split-marker pattern, which must capture the file name.
+fn parse_split_marker(arg: &str) -> Result<Regex, String> {
+    let marker = Regex::new(arg).map_err(|e| e.to_string())?;
+    if marker.captures_len() < 2 {
+        return Err(String::from("expected a capture group for the file name"));
     }
+    Ok(marker)
 }
 
-#[cfg(test)]
-mod tests {
-    use assert_cmd::prelude::*;
-    use std::io::Write;
-    use std::path::PathBuf;
-    use std::process::Command;
-    use std::process::Stdio;
+// Parse a --rate-limit: a positive number of bytes, with an optional K, M or G suffix for
+// powers of 1024.
+fn parse_rate(arg: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match arg.char_indices().last() {
+        Some((idx, 'K')) => (&arg[..idx], 1 << 10),
+        Some((idx, 'M')) => (&arg[..idx], 1 << 20),
+        Some((idx, 'G')) => (&arg[..idx], 1 << 30),
+        _ => (arg, 1),
+    };
 
-    // Test cat of a single file without formatting.
-    #[test]
-    fn test_cat_no_formatting() {
-        let mut cmd = Command::cargo_bin("cat").unwrap();
-        let mut test_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        test_path.push("tests/test.txt");
-        let expected_output: Vec<u8> = vec![
-            116, 101, 115, 116, 9, 9, 10, 10, 10, 10, 9, 9, 116, 101, 115, 116, 10, 116, 101, 115,
-            116, 10, 0, 1, 2, 3, 10, 127, 10, 128, 129, 10, 160, 161, 10, 255, 10,
-        ];
+    match digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+    {
+        Some(rate) if rate > 0 => Ok(rate),
+        _ => Err(String::from(
+            "expected a positive number of bytes, optionally with K, M or G",
+        )),
+    }
+}
 
-        let output = cmd
-            .arg(test_path.into_os_string().into_string().unwrap())
-            .output()
-            .unwrap();
-        assert_eq!(output.stdout, expected_output);
+// Parse a command line argument that must be a single byte, after escapes.
+fn parse_byte(arg: &str) -> Result<u8, String> {
+    match unescape(arg).as_bytes() {
+        [c] => Ok(*c),
+        _ => Err(String::from("expected a single byte")),
     }
+}
 
-    // Test cat of a single file with different formatting options.
-    #[test]
-    fn test_cat_with_formatting() {
-        let mut cmd = Command::cargo_bin("cat").unwrap();
-        let mut test_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        test_path.push("tests/test.txt");
-        let test_string = test_path.into_os_string().into_string().unwrap();
-        let mut expected_output: Vec<u8> = vec![
-            49, 32, 116, 101, 115, 116, 94, 73, 94, 73, 36, 10, 50, 32, 36, 10, 51, 32, 94, 73, 94,
-            73, 116, 101, 115, 116, 36, 10, 52, 32, 116, 101, 115, 116, 36, 10, 53, 32, 94, 64, 94,
-            65, 94, 66, 94, 67, 36, 10, 54, 32, 94, 63, 36, 10, 55, 32, 77, 45, 94, 64, 77, 45, 94,
-            65, 36, 10, 56, 32, 77, 45, 32, 77, 45, 33, 36, 10, 57, 32, 77, 45, 94, 63, 36, 10,
-        ];
+// The files -s applies to, for --squeeze-files.
+#[derive(Clone, Debug, PartialEq)]
+enum SqueezeFiles {
+    All,
+    First,
+    Last,
+    // 1-based positions among the FILE operands.
+    Indices(Vec<usize>),
+}
 
-        // Show all, squeeze blanks and show all numbers.
-        let mut output = cmd.arg("-Asn").arg(test_string.clone()).output().unwrap();
-        assert_eq!(output.stdout, expected_output);
+impl SqueezeFiles {
+    // Whether the file at the 0-based index, out of count files, is squeezed.
+    fn includes(&self, index: usize, count: usize) -> bool {
+        match self {
+            SqueezeFiles::All => true,
+            SqueezeFiles::First => index == 0,
+            SqueezeFiles::Last => index + 1 == count,
+            SqueezeFiles::Indices(indices) => indices.contains(&(index + 1)),
+        }
+    }
+}
 
-        // Verify -b option overrides -n.
-        cmd = Command::cargo_bin("cat").unwrap();
-        expected_output = vec![
-            49, 32, 116, 101, 115, 116, 94, 73, 94, 73, 36, 10, 36, 10, 50, 32, 94, 73, 94, 73,
-            116, 101, 115, 116, 36, 10, 51, 32, 116, 101, 115, 116, 36, 10, 52, 32, 94, 64, 94, 65,
-            94, 66, 94, 67, 36, 10, 53, 32, 94, 63, 36, 10, 54, 32, 77, 45, 94, 64, 77, 45, 94, 65,
-            36, 10, 55, 32, 77, 45, 32, 77, 45, 33, 36, 10, 56, 32, 77, 45, 94, 63, 36, 10,
-        ];
-        output = cmd.arg("-Asnb").arg(test_string.clone()).output().unwrap();
-        assert_eq!(output.stdout, expected_output);
+// Parse --squeeze-files: all, first, last or a comma-separated list of 1-based positions.
+fn parse_squeeze_files(arg: &str) -> Result<SqueezeFiles, String> {
+    match arg {
+        "all" => Ok(SqueezeFiles::All),
+        "first" => Ok(SqueezeFiles::First),
+        "last" => Ok(SqueezeFiles::Last),
+        _ => arg
+            .split(',')
+            .map(|index| match index.parse::<usize>() {
+                Ok(index) if index > 0 => Ok(index),
+                _ => Err(format!(
+                    "expected all, first, last or file positions from 1, not '{}'",
+                    index
+                )),
+            })
+            .collect::<Result<_, _>>()
+            .map(SqueezeFiles::Indices),
     }
+}
 
-    // Test cat of multiple files and stdin.
-    #[test]
-    fn test_cat_multiple() {
-        let mut cmd = Command::cargo_bin("cat").unwrap();
-        let mut test_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        test_path.push("tests/test.txt");
-        let test_string = test_path.into_os_string().into_string().unwrap();
-        let expected_output: Vec<u8> = vec![
-            49, 32, 116, 101, 115, 116, 94, 73, 94, 73, 36, 10, 50, 32, 36, 10, 51, 32, 94, 73, 94,
-            73, 116, 101, 115, 116, 36, 10, 52, 32, 116, 101, 115, 116, 36, 10, 53, 32, 94, 64, 94,
-            65, 94, 66, 94, 67, 36, 10, 54, 32, 94, 63, 36, 10, 55, 32, 77, 45, 94, 64, 77, 45, 94,
-            65, 36, 10, 56, 32, 77, 45, 32, 77, 45, 33, 36, 10, 57, 32, 77, 45, 94, 63, 36, 10, 49,
-            48, 32, 116, 101, 115, 116, 36, 10, 49, 49, 32, 116, 101, 115, 116, 94, 73, 94, 73, 36,
-            10, 49, 50, 32, 36, 10, 49, 51, 32, 94, 73, 94, 73, 116, 101, 115, 116, 36, 10, 49, 52,
-            32, 116, 101, 115, 116, 36, 10, 49, 53, 32, 94, 64, 94, 65, 94, 66, 94, 67, 36, 10, 49,
-            54, 32, 94, 63, 36, 10, 49, 55, 32, 77, 45, 94, 64, 77, 45, 94, 65, 36, 10, 49, 56, 32,
-            77, 45, 32, 77, 45, 33, 36, 10, 49, 57, 32, 77, 45, 94, 63, 36, 10,
-        ];
+// A --number-format string, split around its single integer conversion.
+#[derive(Clone, Debug)]
+struct NumberFormat {
+    before: String,
+    after: String,
+    width: usize,
+    zero_pad: bool,
+    left_align: bool,
+}
 
-        // File, stdin, file.
-        let mut child = cmd
-            .arg("-Asn")
-            .arg(test_string.clone())
-            .arg("-")
-            .arg(test_string.clone())
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()
-            .unwrap();
+impl NumberFormat {
+    fn render(&self, line_number: i64) -> String {
+        let width = self.width;
+        let number = if self.left_align {
+            format!("{:<width$}", line_number)
+        } else if self.zero_pad {
+            format!("{:0width$}", line_number)
+        } else {
+            format!("{:>width$}", line_number)
+        };
+        format!("{}{}{}", self.before, number, self.after)
+    }
+}
 
-        // Collect stdin, send a message and drop to close.
-        let stdin = child.stdin.as_mut().unwrap();
-        stdin.write_all(b"test\n").unwrap();
+// Parse the printf subset accepted by --number-format: literal text, %% and exactly one
+// %[-0][width]d conversion.
+fn parse_number_format(arg: &str) -> Result<NumberFormat, String> {
+    let mut format = NumberFormat {
+        before: String::new(),
+        after: String::new(),
+        width: 0,
+        zero_pad: false,
+        left_align: false,
+    };
+    let mut seen_conversion = false;
+    let mut chars = unescape(arg)
+        .chars()
+        .collect::<Vec<_>>()
+        .into_iter()
+        .peekable();
 
-        let output = child.wait_with_output().unwrap();
-        assert_eq!(output.stdout, expected_output);
+    while let Some(c) = chars.next() {
+        let text = if seen_conversion {
+            &mut format.after
+        } else {
+            &mut format.before
+        };
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+        if chars.next_if_eq(&'%').is_some() {
+            text.push('%');
+            continue;
+        }
+
+        while let Some(flag) = chars.next_if(|&c| c == '-' || c == '0') {
+            match flag {
+                '-' => format.left_align = true,
+                _ => format.zero_pad = true,
+            }
+        }
+        let mut width = String::new();
+        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+            width.push(digit);
+        }
+
+        match chars.next() {
+            Some('d') if seen_conversion => {
+                return Err(String::from("expected exactly one %d conversion"))
+            }
+            Some('d') => {
+                format.width = width.parse().unwrap_or(0);
+                seen_conversion = true;
+            }
+            Some(other) => return Err(format!("unsupported conversion '%{}'", other)),
+            None => return Err(String::from("incomplete conversion at the end")),
+        }
+    }
+
+    if !seen_conversion {
+        return Err(String::from("expected exactly one %d conversion"));
+    }
+    Ok(format)
+}
+
+// Expand the \t, \n and \\ escapes of a command line string.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('\\') => unescaped.push('\\'),
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+// Length of the line without its trailing newline.
+fn content_len(line: &[u8]) -> usize {
+    line.len() - usize::from(line.last() == Some(&b'\n'))
+}
+
+// Replace each ${VAR} with the value of the environment variable and $$ with a literal $.
+// Unset variables expand to nothing, or are an error when strict.
+fn expand_env(line: &[u8], strict: bool) -> io::Result<Vec<u8>> {
+    let mut expanded = Vec::with_capacity(line.len());
+    let mut idx = 0;
+
+    while idx < line.len() {
+        match &line[idx..] {
+            [b'$', b'$', ..] => {
+                expanded.push(b'$');
+                idx += 2;
+            }
+            [b'$', b'{', rest @ ..] if rest.contains(&b'}') => {
+                let end = rest.iter().position(|&c| c == b'}').unwrap();
+                let name = String::from_utf8_lossy(&rest[..end]);
+
+                match std::env::var_os(&*name) {
+                    Some(value) => expanded.extend_from_slice(value.as_encoded_bytes()),
+                    None if strict => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!("{}: environment variable not set", name),
+                        ))
+                    }
+                    None => {}
+                }
+
+                idx += end + 3;
+            }
+            _ => {
+                expanded.push(line[idx]);
+                idx += 1;
+            }
+        }
+    }
+
+    Ok(expanded)
+}
+
+// Render a line number, padded as requested by --number-pad and --number-width.
+fn format_number(args: &Args, line_number: i64) -> String {
+    if args.number_pad.is_none() && args.number_width.is_none() {
+        return line_number.to_string();
+    }
+
+    let width = args.number_width.unwrap_or(6);
+    match args.number_pad.unwrap_or(NumberPad::Space) {
+        NumberPad::Space => format!("{:>width$}", line_number),
+        NumberPad::Zero => format!("{:0width$}", line_number),
+    }
+}
+
+// Render a byte in ^ and M- notation, except for LFD and TAB.
+fn non_printing(c: u8) -> Vec<u8> {
+    if c < 32 && c != b'\n' && c != b'\t' {
+        vec![b'^', c + 64]
+    } else if c == 127 {
+        vec![b'^', b'?']
+    } else if c > 127 {
+        if c >= 128 + 32 {
+            if c < 255 {
+                vec![b'M', b'-', c - 128]
+            } else {
+                vec![b'M', b'-', b'^', b'?']
+            }
+        } else {
+            vec![b'M', b'-', b'^', c - 128 + 64]
+        }
+    } else {
+        vec![c]
+    }
+}
+
+// Render a control byte, except for LFD and TAB, as its Unicode Control Pictures symbol.
+fn control_picture(c: u8) -> Vec<u8> {
+    let picture = match c {
+        b'\n' | b'\t' => return vec![c],
+        0..=31 => 0x2400 + u32::from(c),
+        127 => 0x2421,
+        _ => return vec![c],
+    };
+
+    char::from_u32(picture).map_or(vec![c], |picture| picture.to_string().into_bytes())
+}
+
+// Replace each tab with the marker padded with spaces up to the tab stop the tab would have
+// reached, moving to later stops until the marker fits.
+fn show_tabs_aligned(line: &mut Vec<u8>, marker: &str) {
+    let mut aligned = Vec::with_capacity(line.len());
+    let mut column = 0;
+    let width = marker.chars().count();
+
+    for &c in line.iter() {
+        if c == b'\t' {
+            let mut stop = (column / TAB_WIDTH + 1) * TAB_WIDTH;
+            while stop - column < width {
+                stop += TAB_WIDTH;
+            }
+
+            aligned.extend_from_slice(marker.as_bytes());
+            aligned.resize(aligned.len() + stop - column - width, b' ');
+            column = stop;
+        } else {
+            aligned.push(c);
+
+            // UTF-8 continuation bytes do not start a new column.
+            if c & 0xc0 != 0x80 {
+                column += 1;
+            }
+        }
+    }
+
+    *line = aligned;
+}
+
+// Length of the -v escape sequence (or plain byte) starting at the given index.
+fn escape_len(line: &[u8], idx: usize) -> usize {
+    match &line[idx..] {
+        [b'M', b'-', b'^', _, ..] => 4,
+        [b'M', b'-', _, ..] => 3,
+        [b'^', _, ..] => 2,
+        _ => 1,
+    }
+}
+
+// Break a line into rows of at most `width` columns, never splitting an escape sequence.
+fn wrap_escaped(line: &mut Vec<u8>, width: usize) {
+    let content_len = content_len(line);
+    let mut wrapped: Vec<u8> = Vec::with_capacity(line.len());
+    let mut column = 0;
+    let mut idx = 0;
+
+    while idx < content_len {
+        let len = escape_len(&line[..content_len], idx);
+
+        if column > 0 && column + len > width {
+            wrapped.push(b'\n');
+            column = 0;
+        }
+
+        wrapped.extend_from_slice(&line[idx..idx + len]);
+        column += len;
+        idx += len;
+    }
+
+    wrapped.extend_from_slice(&line[content_len..]);
+    *line = wrapped;
+}
+
+// Cut the line to at most `width` columns, ending it with the marker when anything was cut.
+// Each UTF-8 character counts as one column and escape sequences are never split.
+fn truncate_line(line: &mut Vec<u8>, width: usize, marker: &str) {
+    let columns = |bytes: &[u8]| bytes.iter().filter(|&&c| c & 0xC0 != 0x80).count();
+    let content_len = content_len(line);
+
+    if columns(&line[..content_len]) <= width {
+        return;
+    }
+
+    let room = width.saturating_sub(columns(marker.as_bytes()));
+    let mut column = 0;
+    let mut idx = 0;
+
+    while idx < content_len {
+        let mut len = escape_len(&line[..content_len], idx);
+        while idx + len < content_len && line[idx + len] & 0xC0 == 0x80 {
+            len += 1;
+        }
+
+        let unit_columns = columns(&line[idx..idx + len]);
+        if column + unit_columns > room {
+            break;
+        }
+        column += unit_columns;
+        idx += len;
+    }
+
+    line.splice(idx..content_len, marker.bytes());
+}
+
+// Width of the terminal attached to stdout, falling back to $COLUMNS and then 80.
+fn terminal_width() -> usize {
+    #[cfg(unix)]
+    {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+
+        // SAFETY: TIOCGWINSZ only writes into the provided winsize struct.
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+            && size.ws_col > 0
+        {
+            return usize::from(size.ws_col);
+        }
+    }
+
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .filter(|&columns| columns > 0)
+        .unwrap_or(80)
+}
+
+// Reverse the UTF-8 characters of a buffer in place; invalid bytes are reversed one by one.
+fn reverse_chars(content: &mut [u8]) {
+    let mut units: Vec<&[u8]> = Vec::new();
+
+    for chunk in content.utf8_chunks() {
+        let valid = chunk.valid();
+        units.extend(
+            valid
+                .char_indices()
+                .map(|(idx, c)| &valid.as_bytes()[idx..idx + c.len_utf8()]),
+        );
+        units.extend(chunk.invalid().chunks(1));
+    }
+
+    let reversed: Vec<u8> = units.into_iter().rev().flatten().copied().collect();
+    content.copy_from_slice(&reversed);
+}
+
+// Rewrite the bytes that are not valid UTF-8 so they stand out.
+fn show_invalid_utf8(line: &[u8], invalid: Invalid) -> Vec<u8> {
+    let mut shown = Vec::with_capacity(line.len());
+
+    for chunk in line.utf8_chunks() {
+        shown.extend_from_slice(chunk.valid().as_bytes());
+
+        let bytes = chunk.invalid();
+        if bytes.is_empty() {
+            continue;
+        }
+        match invalid {
+            Invalid::Replace => shown.extend_from_slice("\u{FFFD}".as_bytes()),
+            Invalid::Escape => shown.extend(bytes.iter().flat_map(|c| non_printing(*c))),
+            Invalid::Marker => {
+                for c in bytes {
+                    shown.extend_from_slice(format!("<0x{:02X}>", c).as_bytes());
+                }
+            }
+        }
+    }
+
+    shown
+}
+
+// Strip the leading whitespace shared by all nonblank lines; blank lines are left empty.
+fn dedent(content: &[u8]) -> Vec<u8> {
+    let is_blank = |line: &[u8]| line.iter().all(|c| c.is_ascii_whitespace());
+    let mut indent: Option<&[u8]> = None;
+
+    for line in content.split_inclusive(|&c| c == b'\n') {
+        if is_blank(line) {
+            continue;
+        }
+
+        let len = line
+            .iter()
+            .take_while(|&&c| c == b' ' || c == b'\t')
+            .count();
+        indent = Some(match indent {
+            Some(common) => {
+                let shared = common.iter().zip(line).take_while(|(a, b)| a == b).count();
+                &common[..shared.min(len)]
+            }
+            None => &line[..len],
+        });
+    }
+
+    let indent_len = indent.map_or(0, |common| common.len());
+    let mut dedented = Vec::with_capacity(content.len());
+
+    for line in content.split_inclusive(|&c| c == b'\n') {
+        if is_blank(line) {
+            if line.ends_with(b"\n") {
+                dedented.push(b'\n');
+            }
+        } else {
+            dedented.extend_from_slice(&line[indent_len..]);
+        }
+    }
+
+    dedented
+}
+
+// Print a diagnostic to stderr, flushing the output first so both appear in order when they
+// share a terminal or pipe.
+fn warn(out: &mut dyn Write, message: std::fmt::Arguments) {
+    // A failing flush will be reported by the next write.
+    let _ = out.flush();
+    eprintln!("cat: {}", message);
+}
+
+// Format an I/O error the way GNU cat does, without the "(os error N)" suffix.
+fn error_message(e: &io::Error) -> String {
+    let message = e.to_string();
+    match message.find(" (os error ") {
+        Some(idx) => message[..idx].to_string(),
+        None => message,
+    }
+}
+
+// Stream compression applied to the output.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Compression {
+    Gzip,
+    Zstd,
+}
+
+// Standard output, paced by --rate-limit.
+type Stdout = Paced<io::StdoutLock<'static>>;
+
+// Standard output, or a --split or --split-marker file, remembering the last byte written for
+// --add-final-newline.
+struct Output<S = Stream> {
+    stream: S,
+    // Newlines translated, for --output-mode=text.
+    text_mode: bool,
+    last_byte: Option<u8>,
+}
+
+// The stream behind the output, optionally compressed.
+enum Stream {
+    Plain(Stdout),
+    // Block buffered, flushed by --flush-interval.
+    Buffered(io::BufWriter<Stdout>),
+    Gzip(GzEncoder<Stdout>),
+    Zstd(zstd::Encoder<'static, Stdout>),
+    // Nothing is written, for --quiet.
+    Discard(io::Sink),
+}
+
+impl Output {
+    fn new(args: &Args) -> io::Result<Output> {
+        Ok(Output {
+            stream: Stream::new(args)?,
+            text_mode: args.output_mode == OutputMode::Text,
+            last_byte: None,
+        })
+    }
+
+    // Terminate the compressed stream, if any, and flush everything to stdout.
+    fn finish(self) -> io::Result<()> {
+        self.stream.finish()
+    }
+}
+
+impl Output<io::BufWriter<File>> {
+    // An output file, written in the same --output-mode as standard output.
+    fn file(args: &Args, file: File) -> Output<io::BufWriter<File>> {
+        Output {
+            stream: io::BufWriter::new(file),
+            text_mode: args.output_mode == OutputMode::Text,
+            last_byte: None,
+        }
+    }
+}
+
+impl<S: Write> Write for Output<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = if self.text_mode {
+            self.stream.write_all(&to_crlf(buf, self.last_byte))?;
+            buf.len()
+        } else {
+            self.stream.write(buf)?
+        };
+        if written > 0 {
+            self.last_byte = Some(buf[written - 1]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Stream {
+    fn new(args: &Args) -> io::Result<Stream> {
+        if args.quiet {
+            return Ok(Stream::Discard(io::sink()));
+        }
+
+        let stdout = Paced::new(io::stdout().lock(), args.rate_limit);
+
+        Ok(match args.compress {
+            None if args.flush_interval.is_some() => Stream::Buffered(io::BufWriter::new(stdout)),
+            None => Stream::Plain(stdout),
+            Some(Compression::Gzip) => {
+                Stream::Gzip(GzEncoder::new(stdout, flate2::Compression::default()))
+            }
+            Some(Compression::Zstd) => Stream::Zstd(zstd::Encoder::new(stdout, 0)?),
+        })
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Stream::Plain(mut stdout) => stdout.flush(),
+            Stream::Buffered(mut stdout) => stdout.flush(),
+            Stream::Gzip(encoder) => encoder.finish()?.flush(),
+            Stream::Zstd(encoder) => encoder.finish()?.flush(),
+            Stream::Discard(_) => Ok(()),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stdout) => stdout.write(buf),
+            Stream::Buffered(stdout) => stdout.write(buf),
+            Stream::Gzip(encoder) => encoder.write(buf),
+            Stream::Zstd(encoder) => encoder.write(buf),
+            Stream::Discard(sink) => sink.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stdout) => stdout.flush(),
+            Stream::Buffered(stdout) => stdout.flush(),
+            Stream::Gzip(encoder) => encoder.flush(),
+            Stream::Zstd(encoder) => encoder.flush(),
+            Stream::Discard(sink) => sink.flush(),
+        }
+    }
+}
+
+// Writer that paces its writes to a number of bytes per second with a token bucket. The
+// bucket holds a tenth of a second of output, so writes come in small steady bursts.
+struct Paced<W> {
+    inner: W,
+    rate: Option<u64>,
+    tokens: f64,
+    last: Instant,
+}
+
+impl<W: Write> Paced<W> {
+    fn new(inner: W, rate: Option<u64>) -> Paced<W> {
+        let mut paced = Paced {
+            inner,
+            rate,
+            tokens: 0.0,
+            last: Instant::now(),
+        };
+        paced.tokens = paced.capacity();
+        paced
+    }
+
+    fn capacity(&self) -> f64 {
+        self.rate.map_or(0.0, |rate| (rate as f64 / 10.0).max(1.0))
+    }
+}
+
+impl<W: Write> Write for Paced<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(rate) = self.rate else {
+            return self.inner.write(buf);
+        };
+
+        // Wait until the bucket holds enough tokens for as much of the buffer as fits in it.
+        let len = buf.len().min(self.capacity() as usize);
+        loop {
+            let now = Instant::now();
+            let refill = now.duration_since(self.last).as_secs_f64() * rate as f64;
+            self.tokens = (self.tokens + refill).min(self.capacity());
+            self.last = now;
+
+            if self.tokens >= len as f64 {
+                break;
+            }
+            thread::sleep(Duration::from_secs_f64(
+                (len as f64 - self.tokens) / rate as f64,
+            ));
+        }
+
+        let written = self.inner.write(&buf[..len])?;
+        self.tokens -= written as f64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Turn each LF into CRLF, like the C runtime does for a stream in text mode, but leave an LF
+// that already follows a CR alone. `previous` is the byte written just before `buf`. Rust
+// writes to the handle directly on every platform, so the translation is done here rather than
+// with _setmode.
+fn to_crlf(buf: &[u8], mut previous: Option<u8>) -> Vec<u8> {
+    let mut translated = Vec::with_capacity(buf.len() + buf.len() / 16);
+    for &c in buf {
+        if c == b'\n' && previous != Some(b'\r') {
+            translated.push(b'\r');
+        }
+        translated.push(c);
+        previous = Some(c);
+    }
+    translated
+}
+
+// Running digest of the input for --hash-check.
+enum Digest {
+    Crc32(crc32fast::Hasher),
+    Sha256(sha2::Sha256),
+}
+
+impl Digest {
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Digest::Crc32(hasher) => hasher.update(bytes),
+            Digest::Sha256(hasher) => hasher.update(bytes),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Digest::Crc32(_) => "crc32",
+            Digest::Sha256(_) => "sha256",
+        }
+    }
+
+    // Lowercase hex of the digest so far.
+    fn hex(&self) -> String {
+        match self {
+            Digest::Crc32(hasher) => format!("{:08x}", hasher.clone().finalize()),
+            Digest::Sha256(hasher) => hasher
+                .clone()
+                .finalize()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        }
+    }
+}
+
+// Parse an expected digest: "ALGORITHM:HEX" or a bare SHA-256, optionally followed by a file
+// name as in sha256sum output.
+fn parse_digest(content: &str) -> Result<(Digest, String), String> {
+    let token = content.split_whitespace().next().unwrap_or("");
+    let (algorithm, hex) = token.split_once(':').unwrap_or(("sha256", token));
+
+    let digest = match algorithm.to_ascii_lowercase().as_str() {
+        "crc32" => Digest::Crc32(crc32fast::Hasher::new()),
+        "sha256" => Digest::Sha256(sha2::Sha256::new()),
+        other => return Err(format!("unsupported digest algorithm '{}'", other)),
+    };
+    if hex.is_empty() || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(String::from("no hexadecimal digest found"));
+    }
+
+    Ok((digest, hex.to_ascii_lowercase()))
+}
+
+// Feeds everything read through it into the --hash-check digest.
+struct HashingReader {
+    inner: Box<dyn BufRead>,
+    digest: Rc<RefCell<Digest>>,
+}
+
+impl Read for HashingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.digest.borrow_mut().update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+// Counters for the --summary report. Bytes and lines count the FILE operands as read.
+#[derive(Default)]
+struct Summary {
+    bytes: u64,
+    lines: u64,
+    files: u64,
+    failed: u64,
+    squeezed: u64,
+    // Last byte of the current input, to count a final line without a newline.
+    last_byte: Option<u8>,
+}
+
+// Counts everything read through it into the --summary report.
+struct CountingReader {
+    inner: Box<dyn BufRead>,
+    summary: Rc<RefCell<Summary>>,
+}
+
+impl Read for CountingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            let mut summary = self.summary.borrow_mut();
+            summary.bytes += n as u64;
+            summary.lines += buf[..n].iter().filter(|&&c| c == b'\n').count() as u64;
+            summary.last_byte = Some(buf[n - 1]);
+        }
+        Ok(n)
+    }
+}
+
+// Count a blank line dropped by squeezing in the --summary report.
+fn count_squeezed(state: &State) {
+    if let Some(summary) = &state.summary {
+        summary.borrow_mut().squeezed += 1;
+    }
+}
+
+// State carried across input files.
+struct State {
+    // Line number, increases across files.
+    line_number: i64,
+    // Input lines read so far.
+    lines_read: u64,
+    // Consecutive blank lines seen, for squeezing.
+    newlines: u64,
+    // Blank lines held back by --trim-blank-runs-to.
+    pending_blanks: Vec<Vec<u8>>,
+    // Output file of the current --split-marker section.
+    section: Option<Output<io::BufWriter<File>>>,
+    // Blank line kept from the current run, held back until the run ends.
+    held_blank: Option<Vec<u8>>,
+    // Whether the held back blank run touches a file boundary.
+    at_seam: bool,
+    // Name of the current input, as printed by --with-filename.
+    file_name: String,
+    // Last byte read from the current input, if any.
+    last_byte: Option<u8>,
+    // Input line index of the last line kept by --sample, for --group-separator.
+    last_sampled: Option<u64>,
+    // Whether the last line printed has no newline, for --ensure-newline-between-files.
+    unterminated: bool,
+    // Bytes of the current line already printed by --partial-line-timeout.
+    partial_len: usize,
+    // Input bytes copied so far by --head-bytes.
+    bytes_read: u64,
+    // Last bytes of the input kept by --tail-bytes.
+    tail: VecDeque<u8>,
+    // Occurrences of each byte value for --byte-histogram.
+    histogram: [u64; 256],
+    // When the output was last flushed by --flush-interval.
+    last_flush: Instant,
+    // Whether the last timestamped line was inside the --since/--until window.
+    in_window: bool,
+    // Whether the --verbose note about -sn was printed.
+    noted: bool,
+    // Lines read from the current input.
+    file_line: u64,
+    // Whether --assert-max-width found a line that is too wide.
+    too_wide: bool,
+    // The --unique-field value of the previous line, if the pattern matched it.
+    last_field: Option<Vec<u8>>,
+    // Counters for --summary, shared with the reader of the current input.
+    summary: Option<Rc<RefCell<Summary>>>,
+    // Unterminated last line of the previous input, which the next input may continue.
+    carry: Vec<u8>,
+    // Name of the input the carried line started in, for -H.
+    carry_name: Option<String>,
+    // Whether the carried line is being printed because no input follows it.
+    at_end: bool,
+    // Position of the current input among the FILE operands.
+    file_index: Option<usize>,
+    // The rest of the --diff-base baseline, read alongside the input.
+    baseline: Option<Box<dyn BufRead>>,
+    // Whether --diff-base found a changed line.
+    differs: bool,
+    // Whether --validate-utf8 found an invalid sequence.
+    invalid_utf8: bool,
+    // Whether --fail-on-binary found a binary input.
+    binary: bool,
+    // Whether --on-missing=skip skipped the current input.
+    skipped: bool,
+    // Digest of all the input so far, for --hash-check.
+    digest: Option<Rc<RefCell<Digest>>>,
+    // Highlighter for the current file.
+    #[cfg(feature = "highlight")]
+    highlighter: Option<highlight::Highlighter>,
+}
+
+impl State {
+    fn new(args: &Args) -> State {
+        State {
+            line_number: args.start_number,
+            lines_read: 0,
+            newlines: 0,
+            pending_blanks: Vec::new(),
+            section: None,
+            held_blank: None,
+            at_seam: false,
+            file_name: String::new(),
+            last_byte: None,
+            last_sampled: None,
+            unterminated: false,
+            partial_len: 0,
+            bytes_read: 0,
+            tail: VecDeque::new(),
+            histogram: [0; 256],
+            last_flush: Instant::now(),
+            in_window: false,
+            noted: false,
+            file_line: 0,
+            too_wide: false,
+            last_field: None,
+            summary: args
+                .summary
+                .then(|| Rc::new(RefCell::new(Summary::default()))),
+            carry: Vec::new(),
+            carry_name: None,
+            at_end: false,
+            file_index: None,
+            baseline: None,
+            differs: false,
+            invalid_utf8: false,
+            binary: false,
+            skipped: false,
+            digest: None,
+            #[cfg(feature = "highlight")]
+            highlighter: None,
+        }
+    }
+}
+
+// Open the input for reading, with --read-timeout, --stdin-timeout, --partial-line-timeout and
+// --flush-interval applied on Unix.
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn open_input(args: &Args, file: &String) -> io::Result<Box<dyn BufRead>> {
+    // Whichever way the input is polled, --flush-interval also wakes up when it goes idle.
+    #[cfg(unix)]
+    let idle = args.flush_interval.map(Duration::from_millis);
+
+    #[cfg(unix)]
+    if let Some(timeout) = args.read_timeout {
+        use std::os::unix::fs::OpenOptionsExt;
+        let timeout = Duration::from_millis(timeout);
+
+        if file == FILENAME_STDIN {
+            return Ok(buffered(
+                args,
+                TimeoutReader::new(stdin_fd()?, timeout).with_idle(idle),
+            ));
+        }
+
+        // Open without blocking so a FIFO with no writer cannot hang, then restore blocking
+        // reads and leave the waiting to the timeout.
+        let file = File::options()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(file)?;
+        set_blocking(&file)?;
+        return Ok(buffered(
+            args,
+            TimeoutReader::new(file, timeout).with_idle(idle),
+        ));
+    }
+
+    // Give up on a terminal that has not sent anything after --stdin-timeout.
+    #[cfg(unix)]
+    if file == FILENAME_STDIN {
+        use std::io::IsTerminal;
+
+        if let Some(timeout) = args.stdin_timeout.filter(|_| io::stdin().is_terminal()) {
+            let timeout = Duration::from_millis(timeout);
+            return Ok(buffered(
+                args,
+                TimeoutReader::for_first_read(stdin_fd()?, timeout).with_idle(idle),
+            ));
+        }
+    }
+
+    // Wait for data at most the --partial-line-timeout, so a partial line can be printed.
+    #[cfg(unix)]
+    if let Some(timeout) = args.partial_line_timeout {
+        let timeout = Duration::from_millis(timeout);
+
+        if file == FILENAME_STDIN {
+            return Ok(buffered(
+                args,
+                TimeoutReader::new(stdin_fd()?, timeout).with_idle(idle),
+            ));
+        }
+
+        return Ok(buffered(
+            args,
+            TimeoutReader::new(File::open(file)?, timeout).with_idle(idle),
+        ));
+    }
+
+    // Notice when the input goes idle, so the buffered output can be flushed meanwhile.
+    #[cfg(unix)]
+    if let Some(interval) = args.flush_interval {
+        let interval = Duration::from_millis(interval);
+        let input = if file == FILENAME_STDIN {
+            stdin_fd()?
+        } else {
+            File::open(file)?
+        };
+        return Ok(buffered(args, TimeoutReader::for_idle(input, interval)));
+    }
+
+    if file == FILENAME_STDIN {
+        // Stdin keeps its own read-ahead buffer, so read the descriptor directly when nothing
+        // may be read ahead.
+        #[cfg(unix)]
+        if args.input_buffering == InputBuffering::None {
+            return Ok(buffered(args, stdin_fd()?));
+        }
+
+        // Read from stdin.
+        Ok(buffered(args, io::stdin()))
+    } else {
+        Ok(buffered(args, File::open(file)?))
+    }
+}
+
+// A duplicate of the stdin descriptor, to poll and read directly. Stdin keeps its own
+// read-ahead buffer, and data waiting there would go unnoticed by poll.
+#[cfg(unix)]
+fn stdin_fd() -> io::Result<File> {
+    use std::os::fd::AsFd;
+    Ok(File::from(io::stdin().as_fd().try_clone_to_owned()?))
+}
+
+// Wrap the reader in a buffer sized by --input-buffering.
+fn buffered(args: &Args, reader: impl Read + 'static) -> Box<dyn BufRead> {
+    let capacity = match args.input_buffering {
+        InputBuffering::Full => 64 * 1024,
+        InputBuffering::Line => 1024,
+        InputBuffering::None => 1,
+    };
+
+    Box::new(BufReader::with_capacity(capacity, reader))
+}
+
+// Sniff the byte order mark and transcode UTF-16 to UTF-8; input without a BOM passes
+// through untouched.
+fn decode_bom(mut reader: Box<dyn BufRead>) -> io::Result<Box<dyn BufRead>> {
+    // A short first read must not hide a BOM, so gather up to three bytes.
+    let mut head = Vec::with_capacity(3);
+    while head.len() < 3 {
+        let available = reader.fill_buf()?;
+        if available.is_empty() {
+            break;
+        }
+        let n = available.len().min(3 - head.len());
+        head.extend_from_slice(&available[..n]);
+        reader.consume(n);
+    }
+
+    let big_endian = match head.as_slice() {
+        [0xEF, 0xBB, 0xBF] => return Ok(reader),
+        [0xFF, 0xFE, ..] => false,
+        [0xFE, 0xFF, ..] => true,
+        _ => return Ok(Box::new(io::Cursor::new(head).chain(reader))),
+    };
+
+    // UTF-16 has no byte-oriented line boundaries, so decode the whole input at once.
+    let mut content = head.split_off(2);
+    reader.read_to_end(&mut content)?;
+
+    let units = content.chunks(2).map(|unit| match unit {
+        [a, b] if big_endian => u16::from_be_bytes([*a, *b]),
+        [a, b] => u16::from_le_bytes([*a, *b]),
+        // A dangling odd byte cannot be decoded.
+        _ => 0xFFFD,
+    });
+    let decoded: String = char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+
+    Ok(Box::new(io::Cursor::new(decoded.into_bytes())))
+}
+
+// Add the increment to the niceness of the process.
+#[cfg(unix)]
+fn renice(increment: i32) -> io::Result<()> {
+    // SAFETY: getpriority and setpriority only read and change the priority of this process.
+    // getpriority cannot fail for the calling process, so -1 is always a real niceness.
+    unsafe {
+        let niceness = libc::getpriority(libc::PRIO_PROCESS, 0);
+        let niceness = niceness.saturating_add(increment).clamp(-20, 19);
+        if libc::setpriority(libc::PRIO_PROCESS, 0, niceness) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// Clear O_NONBLOCK on the file descriptor.
+#[cfg(unix)]
+fn set_blocking(fd: &impl AsRawFd) -> io::Result<()> {
+    let fd = fd.as_raw_fd();
+
+    // SAFETY: fcntl only reads and updates the status flags of a descriptor we own.
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags == -1 || libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+// Reader that fails with a timeout when no data arrives in time, either on every read or only
+// until the first data arrives. With an idle interval it also reports WouldBlock whenever it
+// has waited that long in vain, and can be read on.
+#[cfg(unix)]
+struct TimeoutReader<R> {
+    inner: R,
+    timeout: Option<Duration>,
+    idle: Option<Duration>,
+    first_only: bool,
+    started: bool,
+    // When the current wait for data began, kept across WouldBlock.
+    waiting_since: Option<Instant>,
+}
+
+#[cfg(unix)]
+impl<R: Read + AsRawFd> TimeoutReader<R> {
+    fn new(inner: R, timeout: Duration) -> TimeoutReader<R> {
+        TimeoutReader {
+            inner,
+            timeout: Some(timeout),
+            idle: None,
+            first_only: false,
+            started: false,
+            waiting_since: None,
+        }
+    }
+
+    // Only wait a limited time for the first data, then read without a timeout.
+    fn for_first_read(inner: R, timeout: Duration) -> TimeoutReader<R> {
+        TimeoutReader {
+            first_only: true,
+            ..TimeoutReader::new(inner, timeout)
+        }
+    }
+
+    // Never time out, only report the idle intervals.
+    fn for_idle(inner: R, interval: Duration) -> TimeoutReader<R> {
+        TimeoutReader {
+            timeout: None,
+            idle: Some(interval),
+            ..TimeoutReader::new(inner, interval)
+        }
+    }
+
+    // Report WouldBlock after each idle interval spent waiting, if any.
+    fn with_idle(self, idle: Option<Duration>) -> TimeoutReader<R> {
+        TimeoutReader { idle, ..self }
+    }
+}
+
+#[cfg(unix)]
+impl<R: Read + AsRawFd> Read for TimeoutReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = self.timeout.filter(|_| !(self.first_only && self.started));
+        if timeout.is_none() && self.idle.is_none() {
+            return self.inner.read(buf);
+        }
+
+        let mut poll_fd = libc::pollfd {
+            fd: self.inner.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let since = *self.waiting_since.get_or_insert_with(Instant::now);
+
+        loop {
+            let left = timeout.map(|timeout| timeout.saturating_sub(since.elapsed()));
+            let wait = match (left, self.idle) {
+                (Some(left), Some(idle)) => left.min(idle),
+                (left, idle) => left.or(idle).unwrap(),
+            };
+            let wait = i32::try_from(wait.as_millis()).unwrap_or(i32::MAX);
+
+            // SAFETY: poll reads and writes only the single pollfd passed in.
+            match unsafe { libc::poll(&mut poll_fd, 1, wait) } {
+                0 if left.is_some_and(|left| left.as_millis() <= wait as u128) => {
+                    self.waiting_since = None;
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "read timed out"));
+                }
+                0 if self.idle.is_some() => return Err(io::ErrorKind::WouldBlock.into()),
+                0 => {}
+                -1 => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Err(e);
+                    }
+                }
+                _ => {
+                    self.started = true;
+                    self.waiting_since = None;
+                    return self.inner.read(buf);
+                }
+            }
+        }
+    }
+}
+
+// Re-wraps the words of each paragraph, buffering one paragraph at a time. Blank lines
+// separate paragraphs and are kept as they are.
+struct Reflow {
+    inner: Box<dyn BufRead>,
+    width: usize,
+    pending: io::Cursor<Vec<u8>>,
+}
+
+impl Reflow {
+    fn new(inner: Box<dyn BufRead>, width: usize) -> Reflow {
+        Reflow {
+            inner,
+            width,
+            pending: io::Cursor::new(Vec::new()),
+        }
+    }
+
+    // Read the next paragraph and the blank line ending it, if any.
+    fn next_paragraph(&mut self) -> io::Result<Vec<u8>> {
+        let mut words: Vec<Vec<u8>> = Vec::new();
+        let mut separator = Vec::new();
+        let mut ends_with_newline = true;
+
+        loop {
+            let mut line = Vec::new();
+            if self.inner.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+
+            if line.iter().all(|c| c.is_ascii_whitespace()) && line.ends_with(b"\n") {
+                separator = line;
+                break;
+            }
+
+            ends_with_newline = line.ends_with(b"\n");
+            words.extend(
+                line.split(|c| c.is_ascii_whitespace())
+                    .filter(|word| !word.is_empty())
+                    .map(|word| word.to_vec()),
+            );
+        }
+
+        let mut paragraph = Vec::new();
+        let mut column = 0;
+
+        for word in words {
+            // Count characters, not bytes, so UTF-8 text wraps at the same column.
+            let len = word.iter().filter(|&&c| c & 0xC0 != 0x80).count();
+            if column > 0 && column + 1 + len > self.width {
+                paragraph.push(b'\n');
+                column = 0;
+            }
+            if column > 0 {
+                paragraph.push(b' ');
+                column += 1;
+            }
+            paragraph.extend_from_slice(&word);
+            column += len;
+        }
+
+        if column > 0 && ends_with_newline {
+            paragraph.push(b'\n');
+        }
+        paragraph.extend_from_slice(&separator);
+        Ok(paragraph)
+    }
+}
+
+impl Read for Reflow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.pending.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+
+            let paragraph = self.next_paragraph()?;
+            if paragraph.is_empty() {
+                return Ok(0);
+            }
+            self.pending = io::Cursor::new(paragraph);
+        }
+    }
+}
+
+// Create the file of a --split-marker section. The name comes from the input, so refuse to
+// overwrite a file or to write outside the current directory. Names with a directory are
+// refused too, as a symlinked directory could lead anywhere.
+fn create_section(name: &str) -> io::Result<File> {
+    let path = Path::new(name);
+    if name.contains(std::path::is_separator)
+        || !matches!(path.components().next(), Some(Component::Normal(_)))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}: section file name is not a plain file name", name),
+        ));
+    }
+
+    File::options()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", name, error_message(&e))))
+}
+
+// Write the output of a single input to its own --split file, including any blank lines
+// still held back at its end.
+fn split_file(
+    args: &Args,
+    file: &String,
+    mode: Mode,
+    state: &mut State,
+    path: &str,
+) -> io::Result<()> {
+    let mut out = Output::file(args, File::create(path)?);
+
+    // Blank runs do not carry over into another output file.
+    state.newlines = 0;
+    cat(args, file, mode, state, &mut out)?;
+    finish_blanks(args, mode, state, &mut out)?;
+    out.flush()
+}
+
+// Print a --prepend or --append-file file, verbatim unless --wrap-inside is given.
+fn wrap_file(
+    args: &Args,
+    path: &String,
+    mode: Mode,
+    state: &mut State,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    if args.wrap_inside {
+        return cat(args, path, mode, state, out);
+    }
+
+    flush_carry(args, mode, state, out)?;
+    flush_blanks(args, mode, state, out)?;
+    io::copy(&mut File::open(path)?, out)?;
+    Ok(())
+}
+
+// Cat: read from input and print to the output adding formatting if needed.
+fn cat(
+    args: &Args,
+    file: &String,
+    mode: Mode,
+    state: &mut State,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    // Squeezing can be limited to some of the files.
+    let file_args;
+    let (args, mode) = if args.squeeze_blank && !squeezes_file(args, file, state.file_index) {
+        // A blank run does not carry through a file that is not squeezed.
+        state.newlines = 0;
+        file_args = Args {
+            squeeze_blank: false,
+            ..args.clone()
+        };
+        let mode = if mode == Mode::Squeeze {
+            Mode::Copy
+        } else {
+            mode
+        };
+        (&file_args, mode)
+    } else {
+        (args, mode)
+    };
+
+    let mut reader = match open_input(args, file) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound && args.on_missing == OnMissing::Skip => {
+            state.skipped = true;
+            return Ok(());
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound && args.on_missing == OnMissing::Empty => {
+            Box::new(io::empty())
+        }
+        reader => reader?,
+    };
+
+    state.file_name = if file == FILENAME_STDIN {
+        args.stdin_name
+            .clone()
+            .unwrap_or_else(|| String::from("(standard input)"))
+    } else {
+        file.clone()
+    };
+
+    // Like grep, only look at the first buffer of the input.
+    if args.fail_on_binary && reader.fill_buf()?.contains(&0) {
+        state.binary = true;
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "binary file (contains NUL bytes)",
+        ));
+    }
+
+    if let Some(digest) = &state.digest {
+        reader = Box::new(BufReader::new(HashingReader {
+            inner: reader,
+            digest: Rc::clone(digest),
+        }));
+    }
+
+    // Only the FILE operands count toward the summary, not --prepend or --append-file.
+    if let (Some(summary), Some(_)) = (&state.summary, state.file_index) {
+        reader = Box::new(BufReader::new(CountingReader {
+            inner: reader,
+            summary: Rc::clone(summary),
+        }));
+    }
+
+    // Byte-exact passthrough.
+    if args.raw {
+        io::copy(&mut reader, out)?;
+        return Ok(());
+    }
+
+    // Validation looks at the bytes as they are, before any decoding.
+    if args.validate_utf8 {
+        return validate_utf8(&mut reader, state, out);
+    }
+
+    if args.detect_encoding {
+        reader = decode_bom(reader)?;
+    }
+
+    if let Some(width) = args.reflow {
+        reader = Box::new(BufReader::new(Reflow::new(reader, width)));
+    }
+
+    // Dedenting needs the whole file before the first line can be printed.
+    if args.dedent {
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content)?;
+        reader = Box::new(io::Cursor::new(dedent(&content)));
+    }
+
+    // Highlighting is only for humans looking at a terminal, and -v would escape the colors.
+    #[cfg(feature = "highlight")]
+    {
+        state.highlighter =
+            (args.highlight_syntax && !args.show_non_printing && io::stdout().is_terminal())
+                .then(|| highlight::Highlighter::for_file(file))
+                .flatten();
+    }
+
+    // The histogram replaces the content entirely.
+    if args.byte_histogram.is_some() {
+        return count_bytes(&mut reader, state);
+    }
+
+    // The diff replaces the content with the changed lines.
+    if state.baseline.is_some() {
+        return diff_lines(reader.as_mut(), state, out);
+    }
+
+    // Byte-wise head and tail work on the raw stream.
+    if args.head_bytes.is_some() || args.tail_bytes.is_some() {
+        return copy_bytes(args, &mut reader, state, out);
+    }
+
+    // Compare against the system cat on a copy of the input.
+    if args.compat_check {
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content)?;
+        out.flush()?;
+        compat_check(args, file, &content, mode)?;
+        reader = Box::new(io::Cursor::new(content));
+    }
+
+    // Keep the last line of the previous input apart from this one.
+    if args.ensure_newline_between_files && state.unterminated {
+        out.write_all(b"\n")?;
+        state.unterminated = false;
+    }
+
+    // Any blank run from here on touches the boundary with the previous input.
+    state.at_seam = state.lines_read > 0;
+    state.file_line = 0;
+    if args.number_per_file {
+        state.line_number = args.start_number;
+    }
+    state.last_byte = None;
+    if args.read_whole && file != FILENAME_STDIN {
+        // Take a snapshot of the whole file, then write its output in one go.
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content)?;
+        let mut buffer = Vec::new();
+        cat_reader(
+            args,
+            &mut io::Cursor::new(content),
+            mode,
+            state,
+            &mut buffer,
+        )?;
+        out.write_all(&buffer)?;
+    } else {
+        cat_reader(args, &mut reader, mode, state, out)?;
+    }
+
+    if args.warn_no_newline && state.last_byte.is_some_and(|c| c != b'\n') {
+        warn(out, format_args!("{}: no newline at end of file", file));
+    }
+
+    Ok(())
+}
+
+// Copy the reader to the output line by line, adding formatting if needed.
+fn cat_reader(
+    args: &Args,
+    reader: &mut dyn BufRead,
+    mode: Mode,
+    state: &mut State,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    // An unterminated line held back from the previous input is continued by this one.
+    let mut line: Vec<u8> = std::mem::take(&mut state.carry);
+    // The --split-marker file being written, if any.
+    let mut section = state.section.take();
+
+    // Iterate over the reader line by line.
+    loop {
+        match reader.read_until(b'\n', &mut line) {
+            // Like GNU cat, format the last line of an input that lacks a newline together
+            // with the start of the next input.
+            Ok(_) if carries_line(args, mode, state, &line) => {
+                state.last_byte = line.last().copied();
+                state.carry = std::mem::take(&mut line);
+                if state.carry_name.is_none() {
+                    state.carry_name = Some(state.file_name.clone());
+                }
+                break;
+            }
+            // A line partly printed by --partial-line-timeout may end at EOF without new data.
+            Ok(bytes_read) if bytes_read > 0 || !line.is_empty() => {
+                state.lines_read += 1;
+                state.file_line += 1;
+                state.last_byte = line.last().copied();
+
+                let out: &mut dyn Write = match section.as_mut() {
+                    Some(file) => file,
+                    None => &mut *out,
+                };
+
+                // A marker line ends the current section and names the file of the next one.
+                if let Some(name) = args.split_marker.as_ref().and_then(|marker| {
+                    marker
+                        .captures(&line[..content_len(&line)])
+                        .and_then(|captures| captures.get(1))
+                }) {
+                    let path = String::from_utf8_lossy(name.as_bytes()).into_owned();
+                    finish_blanks(args, mode, state, out)?;
+                    out.flush()?;
+
+                    // Blank runs do not carry over into another output file.
+                    state.newlines = 0;
+                    section = Some(Output::file(args, create_section(&path)?));
+                    state.carry_name = None;
+                    line.clear();
+                    continue;
+                }
+
+                let repeated = repeats_field(args, state, &line);
+                let sampled = !repeated
+                    && in_time_window(args, state, &line)
+                    && is_sampled(args, state.lines_read);
+                if sampled {
+                    separate_groups(args, mode, state, out)?;
+                }
+
+                if repeated {
+                    // Collapsed like a squeezed blank line, so it takes no line number.
+                } else if !sampled {
+                    // Skipped lines still take up a line number, unless --dual-number shows
+                    // the original one beside it.
+                    if args.number && !args.dual_number
+                        || (args.number_nonblank && !is_blank_line(&line, args))
+                    {
+                        state.line_number += 1;
+                    }
+                } else if holds_blanks(args) && is_blank_line(&line, args) {
+                    // Hold the blank back; with --squeeze-keep=last each later blank of the run
+                    // replaces it.
+                    if state.held_blank.is_some() {
+                        count_squeezed(state);
+                    }
+                    if state.held_blank.is_none() || args.squeeze_keep == SqueezeKeep::Last {
+                        state.held_blank = Some(line.clone());
+                    }
+                } else if args.trim_blank_runs_to.is_some() && is_blank_line(&line, args) {
+                    // Hold blank lines back until it is known whether their run touches a
+                    // file boundary.
+                    state.pending_blanks.push(line.clone());
+                } else {
+                    flush_blanks(args, mode, state, out)?;
+                    flush_held_blank(args, mode, state, out)?;
+                    if state.partial_len > 0 {
+                        // Print only what follows the part already printed.
+                        let mut formatted = Vec::new();
+                        write_line(args, mode, state, &mut line, &mut formatted)?;
+                        out.write_all(&formatted[state.partial_len.min(formatted.len())..])?;
+                        state.partial_len = 0;
+                    } else {
+                        write_line(args, mode, state, &mut line, out)?;
+                    }
+
+                    if let Some(max_width) = args.assert_max_width {
+                        check_width(max_width, state, &line, out);
+                    }
+                }
+
+                if let Some(interval) = args.flush_interval {
+                    if state.last_flush.elapsed() >= Duration::from_millis(interval) {
+                        out.flush()?;
+                        state.last_flush = Instant::now();
+                    }
+                }
+
+                state.carry_name = None;
+                line.clear();
+            }
+            Ok(_) => break, // EOF.
+            // A non-blocking input with no data yet is not at EOF. Any partial line stays in
+            // the buffer, so wait a little and read on.
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock
+                    && (args.fifo_drain || args.flush_interval.is_some()) =>
+            {
+                // Nothing more is coming for now, so deliver what has been buffered so far.
+                if args.flush_interval.is_some() {
+                    match section.as_mut() {
+                        Some(file) => file.flush()?,
+                        None => out.flush()?,
+                    }
+                    state.last_flush = Instant::now();
+                }
+                if args.fifo_drain {
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+            // No newline arrived in time. The partial line stays in the buffer and is printed
+            // so far; reading carries on either way.
+            Err(e)
+                if e.kind() == io::ErrorKind::TimedOut && args.partial_line_timeout.is_some() =>
+            {
+                let out: &mut dyn Write = match section.as_mut() {
+                    Some(file) => file,
+                    None => &mut *out,
+                };
+                if !line.is_empty() && !is_blank_line(&line, args) {
+                    write_partial(args, mode, state, &line, out)?;
+                } else if args.flush_interval.is_some() {
+                    out.flush()?;
+                    state.last_flush = Instant::now();
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    state.section = section;
+    Ok(())
+}
+
+// Print the part of the line not printed yet, formatting it as if the line ended there. The
+// counters are left alone, so the whole line is numbered and counted once it is complete.
+fn write_partial(
+    args: &Args,
+    mode: Mode,
+    state: &mut State,
+    line: &[u8],
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    flush_blanks(args, mode, state, out)?;
+    flush_held_blank(args, mode, state, out)?;
+
+    let (line_number, newlines, unterminated) =
+        (state.line_number, state.newlines, state.unterminated);
+    let mut formatted = Vec::new();
+    write_line(args, mode, state, &mut line.to_vec(), &mut formatted)?;
+    (state.line_number, state.newlines, state.unterminated) = (line_number, newlines, unterminated);
+
+    out.write_all(&formatted[state.partial_len.min(formatted.len())..])?;
+    state.partial_len = state.partial_len.max(formatted.len());
+    out.flush()
+}
+
+// Whether the line is the unterminated end of an input, to be held back until the next input
+// either continues it or turns out not to exist. Copying needs no look at whole lines.
+fn carries_line(args: &Args, mode: Mode, state: &State, line: &[u8]) -> bool {
+    mode != Mode::Copy
+        && !state.at_end
+        && !args.ensure_newline_between_files
+        && !line.is_empty()
+        && !line.ends_with(b"\n")
+}
+
+// Print the unterminated line held back from the last input, now that no input follows it.
+fn flush_carry(args: &Args, mode: Mode, state: &mut State, out: &mut dyn Write) -> io::Result<()> {
+    if state.carry.is_empty() {
+        return Ok(());
+    }
+
+    state.at_end = true;
+    let result = cat_reader(args, &mut io::empty(), mode, state, out);
+    state.at_end = false;
+    result
+}
+
+// Report the printed line if any of its rows is wider than --assert-max-width. Each UTF-8
+// character counts as one column, and terminal escapes such as the --highlight-syntax colors
+// take none.
+fn check_width(max_width: usize, state: &mut State, line: &[u8], out: &mut dyn Write) {
+    let columns = |row: &[u8]| {
+        let mut columns = 0;
+        let mut idx = 0;
+        while idx < row.len() {
+            if row[idx] == 0x1b && row.get(idx + 1) == Some(&b'[') {
+                // Skip to the final byte of the escape sequence.
+                idx += 2;
+                while idx < row.len() && !(0x40..=0x7e).contains(&row[idx]) {
+                    idx += 1;
+                }
+            } else if row[idx] & 0xC0 != 0x80 {
+                columns += 1;
+            }
+            idx += 1;
+        }
+        columns
+    };
+    let width = line.split(|&c| c == b'\n').map(columns).max().unwrap_or(0);
+
+    if width > max_width {
+        state.too_wide = true;
+        warn(
+            out,
+            format_args!(
+                "{}:{}: line is {} columns wide, more than {}",
+                state.file_name, state.file_line, width, max_width
+            ),
+        );
+    }
+}
+
+// Whether blank lines are held back until the end of their run is known.
+fn holds_blanks(args: &Args) -> bool {
+    args.preserve_blank_before_nonblank_only
+        || args.squeeze_keep == SqueezeKeep::Last
+            && (args.squeeze_blank || args.squeeze_whitespace_lines)
+}
+
+// Print the blank lines still held back at the end of the output. A trailing blank is dropped
+// under --preserve-blank-before-nonblank-only.
+fn finish_blanks(
+    args: &Args,
+    mode: Mode,
+    state: &mut State,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    flush_carry(args, mode, state, out)?;
+
+    if args.preserve_blank_before_nonblank_only && state.held_blank.take().is_some() {
+        count_squeezed(state);
+    }
+
+    flush_held_blank(args, mode, state, out)?;
+    flush_blanks(args, mode, state, out)
+}
+
+// Print the blank line held back by --squeeze-keep=last or
+// --preserve-blank-before-nonblank-only, if any.
+fn flush_held_blank(
+    args: &Args,
+    mode: Mode,
+    state: &mut State,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    match state.held_blank.take() {
+        Some(mut blank) => write_line(args, mode, state, &mut blank, out),
+        None => Ok(()),
+    }
+}
+
+// Print --group-separator before a sampled line that does not follow the previous one.
+fn separate_groups(
+    args: &Args,
+    mode: Mode,
+    state: &mut State,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let Some(separator) = &args.group_separator else {
+        return Ok(());
+    };
+
+    if state
+        .last_sampled
+        .is_some_and(|index| index + 1 != state.lines_read)
+    {
+        flush_blanks(args, mode, state, out)?;
+        out.write_all(separator.as_bytes())?;
+        out.write_all(b"\n")?;
+    }
+    state.last_sampled = Some(state.lines_read);
+    Ok(())
+}
+
+// Print a line read from the input, adding formatting if needed.
+fn write_line(
+    args: &Args,
+    mode: Mode,
+    state: &mut State,
+    line: &mut Vec<u8>,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    // Only the last line of an input can lack a newline, and formatting keeps it that way.
+    state.unterminated = !line.ends_with(b"\n");
+
+    match mode {
+        // Print the buffer as is.
+        Mode::Copy => out.write_all(line.as_slice()),
+        // Fast path for -s alone, skipping the other transforms.
+        Mode::Squeeze => {
+            if !is_blank_line(line, args) {
+                state.newlines = 0;
+                out.write_all(line.as_slice())
+            } else if state.newlines == 0 {
+                state.newlines = 1;
+                out.write_all(line.as_slice())
+            } else {
+                count_squeezed(state);
+                Ok(())
+            }
+        }
+        Mode::Format => {
+            let original = if args.number_per_file {
+                state.file_line
+            } else {
+                state.lines_read
+            };
+            let newlines = state.newlines;
+            #[cfg(feature = "highlight")]
+            let mut highlight = state
+                .highlighter
+                .as_mut()
+                .map(|highlighter| |line: &mut Vec<u8>| highlighter.highlight(line));
+            #[cfg(feature = "highlight")]
+            let highlight = highlight.as_mut().map(|highlight| highlight as Highlight);
+            #[cfg(not(feature = "highlight"))]
+            let highlight = None;
+            format_line(
+                line,
+                args,
+                &mut state.line_number,
+                &mut state.newlines,
+                original,
+                // A line carried over from an earlier input is labelled with that input.
+                args.with_filename
+                    .then_some(state.carry_name.as_deref().unwrap_or(&state.file_name)),
+                highlight,
+            )?;
+            // Only a blank line that took the count past one was squeezed away.
+            if state.newlines > 1 && state.newlines != newlines {
+                count_squeezed(state);
+            }
+
+            // The counter only passes one when -s dropped this line. Users often expect dropped
+            // blanks to still take up numbers.
+            if args.verbose && args.number && state.newlines > 1 && !state.noted {
+                state.noted = true;
+                warn(
+                    out,
+                    format_args!("note: -s drops repeated blank lines before -n numbers them"),
+                );
+            }
+
+            out.write_all(line.as_slice())
+        }
+    }
+}
+
+// Print the blank lines held back by --trim-blank-runs-to, keeping at most the limit when the
+// run touches a file boundary.
+fn flush_blanks(args: &Args, mode: Mode, state: &mut State, out: &mut dyn Write) -> io::Result<()> {
+    let mut blanks = std::mem::take(&mut state.pending_blanks);
+
+    if state.at_seam {
+        blanks.truncate(args.trim_blank_runs_to.unwrap_or(usize::MAX));
+        state.at_seam = false;
+    }
+
+    for mut blank in blanks {
+        write_line(args, mode, state, &mut blank, out)?;
+    }
+
+    Ok(())
+}
+
+// Copy the first --head-bytes bytes of the concatenated input to the output, or keep its last
+// --tail-bytes bytes in the state to be written once all inputs are read.
+fn copy_bytes(
+    args: &Args,
+    reader: &mut dyn BufRead,
+    state: &mut State,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    if let Some(head) = args.head_bytes {
+        let remaining = head.saturating_sub(state.bytes_read);
+        state.bytes_read += io::copy(&mut reader.take(remaining), out)?;
+    } else if let Some(tail) = args.tail_bytes {
+        let tail = usize::try_from(tail).unwrap_or(usize::MAX);
+
+        loop {
+            let buffer = reader.fill_buf()?;
+            if buffer.is_empty() {
+                break;
+            }
+
+            // Only the end of the buffer can survive.
+            let keep = &buffer[buffer.len().saturating_sub(tail)..];
+            state.tail.extend(keep);
+            let excess = state.tail.len().saturating_sub(tail);
+            state.tail.drain(..excess);
+
+            let len = buffer.len();
+            reader.consume(len);
+        }
+    }
+
+    Ok(())
+}
+
+// Add the bytes of the reader to the --byte-histogram counts.
+fn count_bytes(reader: &mut dyn BufRead, state: &mut State) -> io::Result<()> {
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
+        for &c in buffer {
+            state.histogram[usize::from(c)] += 1;
+        }
+
+        let len = buffer.len();
+        reader.consume(len);
+    }
+}
+
+// Report each invalid UTF-8 sequence of the reader with its byte offset. No sequence spans a
+// newline, so the input is checked a line at a time.
+fn validate_utf8(
+    reader: &mut dyn BufRead,
+    state: &mut State,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let mut line = Vec::new();
+    let mut offset = 0;
+
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        let mut idx = 0;
+
+        for chunk in line.utf8_chunks() {
+            idx += chunk.valid().len();
+
+            let bytes = chunk.invalid();
+            if bytes.is_empty() {
+                continue;
+            }
+            state.invalid_utf8 = true;
+
+            let next = line.get(idx + bytes.len()).copied();
+            let nature = match bytes[0] {
+                0x80..=0xbf => "unexpected continuation byte",
+                0xc0 | 0xc1 | 0xf5..=0xff => "byte never used in UTF-8",
+                // The lead byte is fine but the byte after it cannot follow it.
+                _ if next.is_some_and(|c| c & 0xc0 == 0x80) => {
+                    "overlong, surrogate or out-of-range sequence"
+                }
+                _ => "incomplete sequence",
+            };
+            let hex: Vec<String> = bytes.iter().map(|c| format!("{:02x}", c)).collect();
+            writeln!(
+                out,
+                "{}:{}: {} ({})",
+                state.file_name,
+                offset + idx,
+                nature,
+                hex.join(" ")
+            )?;
+
+            idx += bytes.len();
+        }
+
+        offset += line.len();
+        line.clear();
+    }
+
+    Ok(())
+}
+
+// Write the --byte-histogram report.
+fn write_histogram(
+    layout: Histogram,
+    histogram: &[u64; 256],
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    match layout {
+        Histogram::Table => {
+            for (c, &count) in (0..=u8::MAX).zip(histogram).filter(|(_, &count)| count > 0) {
+                let notation = match c {
+                    b'\n' => b"^J".to_vec(),
+                    b'\t' => b"^I".to_vec(),
+                    _ => non_printing(c),
+                };
+                writeln!(
+                    out,
+                    "{:#04x} {:<4} {}",
+                    c,
+                    String::from_utf8_lossy(&notation),
+                    count
+                )?;
+            }
+        }
+        Histogram::Summary => {
+            let total =
+                |range: std::ops::RangeInclusive<usize>| -> u64 { histogram[range].iter().sum() };
+            writeln!(out, "printable {}", total(32..=126))?;
+            writeln!(out, "control {}", total(0..=31) + histogram[127])?;
+            writeln!(out, "non-ASCII {}", total(128..=255))?;
+        }
+    }
+
+    Ok(())
+}
+
+// Print the lines of the reader that differ from the line at the same position of the
+// --diff-base baseline: the baseline line after "- ", then the input line after "+ ". Input
+// lines past the end of the baseline are all printed as added.
+fn diff_lines(reader: &mut dyn BufRead, state: &mut State, out: &mut dyn Write) -> io::Result<()> {
+    let Some(baseline) = state.baseline.as_mut() else {
+        return Ok(());
+    };
+    let mut line = Vec::new();
+    let mut base = Vec::new();
+
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        base.clear();
+        let in_base = baseline.read_until(b'\n', &mut base)? > 0;
+
+        if !in_base || strip_newline(&line) != strip_newline(&base) {
+            state.differs = true;
+            if in_base {
+                write_marked(b'-', &base, out)?;
+            }
+            write_marked(b'+', &line, out)?;
+        }
+        line.clear();
+    }
+
+    Ok(())
+}
+
+// Print the baseline lines no input line was compared with, as removed.
+fn finish_diff(state: &mut State, out: &mut dyn Write) -> io::Result<()> {
+    let Some(baseline) = state.baseline.as_mut() else {
+        return Ok(());
+    };
+    let mut base = Vec::new();
+
+    while baseline.read_until(b'\n', &mut base)? > 0 {
+        state.differs = true;
+        write_marked(b'-', &base, out)?;
+        base.clear();
+    }
+
+    Ok(())
+}
+
+// The line without its LF, if it has one.
+fn strip_newline(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\n").unwrap_or(line)
+}
+
+// Print a --diff-base line after its marker, always ending it with a newline.
+fn write_marked(marker: u8, line: &[u8], out: &mut dyn Write) -> io::Result<()> {
+    out.write_all(&[marker, b' '])?;
+    out.write_all(strip_newline(line))?;
+    out.write_all(b"\n")
+}
+
+// Write the output that needs every input to be read, then finish the output stream.
+fn finish_output(args: &Args, state: &mut State, mut out: Output) -> io::Result<()> {
+    finish_diff(state, &mut out)?;
+
+    if args.add_final_newline && out.last_byte.is_some_and(|c| c != b'\n') {
+        out.write_all(b"\n")?;
+    }
+
+    if let Some(layout) = args.byte_histogram {
+        write_histogram(layout, &state.histogram, &mut out)?;
+    }
+
+    if args.tail_bytes.is_some() {
+        let (front, back) = state.tail.as_slices();
+        out.write_all(front)?;
+        out.write_all(back)?;
+    }
+
+    out.finish()
+}
+
+// Whether -s applies to the file, given --squeeze-files and --squeeze-min-bytes. Stdin and
+// files whose size is unknown pass the size check; --prepend and --append-file are squeezed.
+fn squeezes_file(args: &Args, file: &String, index: Option<usize>) -> bool {
+    if let (Some(which), Some(index)) = (&args.squeeze_files, index) {
+        let count = args
+            .limit_files
+            .map_or(args.files.len(), |limit| limit.min(args.files.len()));
+        if !which.includes(index, count) {
+            return false;
+        }
+    }
+
+    match args.squeeze_min_bytes {
+        Some(min_bytes) if file != FILENAME_STDIN => {
+            std::fs::metadata(file).map_or(true, |metadata| metadata.len() > min_bytes)
+        }
+        _ => true,
+    }
+}
+
+// Whether the 1-based input line is kept by --sample and --sample-offset.
+fn is_sampled(args: &Args, index: u64) -> bool {
+    match args.sample {
+        Some(step) => {
+            let offset = args.sample_offset.unwrap_or(step);
+            index >= offset && (index - offset).is_multiple_of(step)
+        }
+        None => true,
+    }
+}
+
+// Find a cat executable on the PATH other than this one.
+fn find_system_cat() -> Option<PathBuf> {
+    let this_cat = std::env::current_exe()
+        .and_then(|path| path.canonicalize())
+        .ok();
+
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join("cat"))
+        .find(|path| path.is_file() && path.canonicalize().ok() != this_cat)
+}
+
+// Developer check: format the input with both this and the system cat and report where the
+// outputs first differ. Only the GNU options (-vETsnb) are forwarded to the system cat, and
+// the check is skipped when no system cat exists.
+fn compat_check(args: &Args, file: &str, content: &[u8], mode: Mode) -> io::Result<()> {
+    let Some(system_cat) = find_system_cat() else {
+        eprintln!("cat: --compat-check: no system cat found, skipping");
+        return Ok(());
+    };
+
+    let mut ours = Vec::new();
+    let mut state = State::new(args);
+    cat_reader(
+        args,
+        &mut io::Cursor::new(content),
+        mode,
+        &mut state,
+        &mut ours,
+    )?;
+    flush_carry(args, mode, &mut state, &mut ours)?;
+    flush_blanks(args, mode, &mut state, &mut ours)?;
+
+    let gnu_flags = [
+        (args.show_non_printing, "-v"),
+        (args.show_ends, "-E"),
+        (args.show_tabs, "-T"),
+        (args.squeeze_blank, "-s"),
+        (args.number, "-n"),
+        (args.number_nonblank, "-b"),
+    ];
+    let mut child = Command::new(&system_cat)
+        .args(
+            gnu_flags
+                .iter()
+                .filter(|(set, _)| *set)
+                .map(|(_, flag)| flag),
+        )
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Feed the input from a separate thread so a large output cannot block it.
+    let mut stdin = child.stdin.take().unwrap();
+    let input = content.to_vec();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let theirs = child.wait_with_output()?.stdout;
+    writer.join().unwrap()?;
+
+    if ours != theirs {
+        let offset = ours
+            .iter()
+            .zip(&theirs)
+            .position(|(a, b)| a != b)
+            .unwrap_or(ours.len().min(theirs.len()));
+        eprintln!(
+            "cat: {}: output differs from {} at byte {}",
+            file,
+            system_cat.display(),
+            offset
+        );
+    }
+
+    Ok(())
+}
+
+// Expand the aliases and overrides and preprocess the option values, so the rest of the
+// program only has to look at the effective options.
+fn resolve_args(args: &mut Args) {
+    match args.profile {
+        Some(Profile::Log) => args.squeeze_blank = true,
+        Some(Profile::Code) => {
+            args.number = true;
+            args.show_tabs = true;
+        }
+        None => {}
+    }
+
+    // Checked here rather than by clap, as --profile=log turns on -s.
+    for (name, given) in [
+        ("--squeeze-min-bytes", args.squeeze_min_bytes.is_some()),
+        ("--squeeze-files", args.squeeze_files.is_some()),
+    ] {
+        if given && !args.squeeze_blank {
+            Args::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    format!("{} requires -s (--squeeze-blank)", name),
+                )
+                .exit();
+        }
+    }
+
+    if args.e {
+        args.show_ends = true;
+        args.show_non_printing = true;
+    }
+
+    match args.number_style {
+        Some(NumberStyle::All) => args.number = true,
+        Some(NumberStyle::Nonblank) => args.number_nonblank = true,
+        Some(NumberStyle::Off) => {
+            args.number = false;
+            args.number_nonblank = false;
+        }
+        None => {}
+    }
+
+    if args.dual_number {
+        args.number = true;
+    }
+
+    if args.number_nonblank {
+        args.number = false;
+    }
+
+    if args.show_all {
+        args.show_ends = true;
+        args.show_non_printing = true;
+        args.show_tabs = true;
+    }
+
+    if args.t {
+        args.show_non_printing = true;
+        args.show_tabs = true;
+    }
+
+    // -A, -e and -t imply -v, so the conflict can only be seen once they are expanded.
+    if args.control_pictures && args.show_non_printing {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--control-pictures cannot be combined with -v, -A, -e or -t",
+            )
+            .exit();
+    }
+
+    args.line_prefix = args.line_prefix.as_deref().map(unescape);
+    args.line_suffix = args.line_suffix.as_deref().map(unescape);
+    args.group_separator = args.group_separator.as_deref().map(unescape);
+
+    for pair in args.replace.chunks(2) {
+        let (from, to) = (&pair[0], &pair[1]);
+        let pattern = if args.replace_regex {
+            Regex::new(from)
+        } else {
+            Regex::new(&regex::escape(from))
+        };
+
+        match pattern {
+            // Literal replacements must not expand $ references.
+            Ok(pattern) if args.replace_regex => args.replacements.push((pattern, to.clone())),
+            Ok(pattern) => args.replacements.push((pattern, to.replace('$', "$$"))),
+            Err(e) => Args::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("invalid --replace pattern: {}", e),
+                )
+                .exit(),
+        }
+    }
+
+    for pair in args.translate.chunks(2) {
+        match translation_table(&pair[0], &pair[1]) {
+            // Later pairs apply to the output of earlier ones.
+            Ok(table) => {
+                let byte_map = args
+                    .byte_map
+                    .get_or_insert_with(|| Box::new(std::array::from_fn(|c| c as u8)));
+                for c in byte_map.iter_mut() {
+                    *c = table[usize::from(*c)];
+                }
+            }
+            Err(e) => Args::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("invalid --translate sets: {}", e),
+                )
+                .exit(),
+        }
+    }
+
+    if let Some(path) = &args.map_file {
+        let table = std::fs::read(path)
+            .map_err(|e| error_message(&e))
+            .and_then(|table| {
+                <[u8; 256]>::try_from(table.as_slice())
+                    .map_err(|_| format!("expected 256 bytes, found {}", table.len()))
+            });
+        match table {
+            Ok(table) => args.byte_map = Some(Box::new(table)),
+            Err(e) => Args::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("--map-file {}: {}", path, e),
+                )
+                .exit(),
+        }
+    }
+
+    for (name, time) in [("--since", &args.since), ("--until", &args.until)] {
+        let Some(time) = time else {
+            continue;
+        };
+        match parse_timestamp(&args.time_format, time.as_bytes()) {
+            Some(timestamp) if name == "--since" => args.time_window.0 = Some(timestamp),
+            Some(timestamp) => args.time_window.1 = Some(timestamp),
+            None => Args::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("{} '{}' does not match --time-format", name, time),
+                )
+                .exit(),
+        }
+    }
+
+    // A truncated line must still fit its marker.
+    if let Some(width) = args.truncate {
+        if width < args.truncate_marker.chars().count() {
+            Args::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!(
+                        "--truncate {} is narrower than the marker '{}'",
+                        width, args.truncate_marker
+                    ),
+                )
+                .exit();
+        }
+    }
+
+    if args.wrap_nonprinting == Some(0) {
+        args.wrap_nonprinting = Some(terminal_width());
+    }
+}
+
+fn main() -> ExitCode {
+    let mut args = Args::parse();
+
+    resolve_args(&mut args);
+
+    // Check if the input needs to be manipulated before printing.
+    let needs_formatting = args.number
+        || args.number_nonblank
+        || args.number_style.is_some()
+        || args.show_ends
+        || args.show_tabs
+        || args.show_tabs_aligned
+        || args.show_non_printing
+        || args.control_pictures
+        || args.invalid.is_some()
+        || args.reverse_bytes
+        || args.reverse_chars
+        || args.crc_lines
+        || args.show_line_endings
+        || args.upper
+        || args.lower
+        || args.invert_case
+        || args.expand_env
+        || !args.replacements.is_empty()
+        || args.squeeze_whitespace_lines
+        || args.squeeze_spaces
+        || args.squeeze_tabs
+        || args.byte_map.is_some()
+        || args.strip_comments.is_some()
+        || args.truncate.is_some()
+        || args.with_filename
+        || args.line_prefix.is_some()
+        || args.line_suffix.is_some()
+        || args.classify
+        || args.highlight_syntax;
+    let mode = if needs_formatting {
+        Mode::Format
+    } else if args.squeeze_blank {
+        Mode::Squeeze
+    } else {
+        Mode::Copy
+    };
+
+    if args.explain {
+        eprintln!("cat: mode: {:?}", mode);
+        eprintln!("cat: effective options: {:#?}", args);
+    }
+
+    if args.verbose && args.show_line_endings {
+        eprintln!("cat: line endings: ^M$ = CRLF, $ = LF, ^M = CR");
+    }
+
+    // --raw promises exact bytes, so refuse anything that would change them.
+    let transforms = mode != Mode::Copy
+        || args.dedent
+        || args.detect_encoding
+        || args.reflow.is_some()
+        || args.ensure_newline_between_files
+        || args.add_final_newline
+        || args.sample.is_some()
+        || args.prepend.is_some()
+        || args.append_file.is_some()
+        || args.since.is_some()
+        || args.until.is_some()
+        || args.split_marker.is_some()
+        || args.unique_field.is_some()
+        || args.assert_max_width.is_some()
+        || args.preserve_blank_before_nonblank_only
+        || args.trim_blank_runs_to.is_some()
+        || args.head_bytes.is_some()
+        || args.tail_bytes.is_some()
+        || args.byte_histogram.is_some()
+        || args.validate_utf8
+        || args.diff_base.is_some()
+        || args.compress.is_some()
+        || args.output_mode == OutputMode::Text
+        || args.highlight_syntax;
+    if args.raw && transforms {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--raw cannot be combined with options that change the output",
+            )
+            .exit();
+    }
+
+    // Nothing is written before `out` is created below, so the diagnostics up to that point
+    // cannot interleave with the output and go straight to stderr.
+
+    // A missing header or footer would produce a half-wrapped output, so check them first.
+    for path in args.prepend.iter().chain(&args.append_file) {
+        if let Err(e) = File::open(path) {
+            eprintln!("cat: {}: {}", path, error_message(&e));
+            return ExitCode::FAILURE;
+        }
+    }
+
+    // Lower the priority before any input is read.
+    #[cfg(unix)]
+    if let Some(increment) = args.nice {
+        if let Err(e) = renice(increment) {
+            eprintln!("cat: cannot set niceness: {}", error_message(&e));
+        }
+    }
+
+    let mut state = State::new(&args);
+    let mut expected_digest = None;
+    if let Some(path) = &args.hash_check {
+        let parsed = std::fs::read_to_string(path)
+            .map_err(|e| error_message(&e))
+            .and_then(|content| parse_digest(&content));
+        match parsed {
+            Ok((digest, hex)) => {
+                state.digest = Some(Rc::new(RefCell::new(digest)));
+                expected_digest = Some(hex);
+            }
+            Err(e) => {
+                eprintln!("cat: {}: {}", path, e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if let Some(path) = &args.diff_base {
+        match File::open(path) {
+            Ok(file) => state.baseline = Some(Box::new(BufReader::new(file))),
+            Err(e) => {
+                eprintln!("cat: {}: {}", path, error_message(&e));
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let mut out = match Output::new(&args) {
+        Ok(out) => out,
+        Err(e) => {
+            eprintln!("cat: {}", error_message(&e));
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // Whether any of the inputs could not be read.
+    let mut failed = false;
+
+    if let Some(path) = &args.prepend {
+        if let Err(e) = wrap_file(&args, path, mode, &mut state, &mut out) {
+            warn(&mut out, format_args!("{}: {}", path, error_message(&e)));
+            failed = true;
+        }
+    }
+
+    let limit = args.limit_files.unwrap_or(args.files.len());
+
+    for (index, file) in args.files.iter().take(limit).enumerate() {
+        state.file_index = Some(index);
+        let result = match &args.split {
+            Some(prefix) => {
+                let path = format!("{}{}", prefix, index + 1);
+                split_file(&args, file, mode, &mut state, &path)
+            }
+            None => cat(&args, file, mode, &mut state, &mut out),
+        };
+        let skipped = std::mem::take(&mut state.skipped);
+        if let Some(summary) = &state.summary {
+            let mut summary = summary.borrow_mut();
+            if skipped {
+                // A skipped input was not processed, and did not fail either.
+            } else if result.is_ok() {
+                summary.files += 1;
+            } else {
+                summary.failed += 1;
+            }
+            if summary.last_byte.take().is_some_and(|c| c != b'\n') {
+                summary.lines += 1;
+            }
+        }
+        if let Err(e) = result {
+            warn(&mut out, format_args!("{}: {}", file, error_message(&e)));
+            failed = true;
+        }
+    }
+
+    state.file_index = None;
+
+    if args.files.len() > limit {
+        warn(
+            &mut out,
+            format_args!(
+                "--limit-files {}: skipped {} more file(s)",
+                limit,
+                args.files.len() - limit
+            ),
+        );
+    }
+
+    if let Some(path) = &args.append_file {
+        if let Err(e) = wrap_file(&args, path, mode, &mut state, &mut out) {
+            warn(&mut out, format_args!("{}: {}", path, error_message(&e)));
+            failed = true;
+        }
+    }
+
+    if let (Some(digest), Some(expected)) = (&state.digest, &expected_digest) {
+        let digest = digest.borrow();
+        if digest.hex() != *expected {
+            warn(
+                &mut out,
+                format_args!(
+                    "--hash-check: {} mismatch: expected {}, got {}",
+                    digest.name(),
+                    expected,
+                    digest.hex()
+                ),
+            );
+            failed = true;
+        }
+    }
+
+    if let Some(mut section) = state.section.take() {
+        if let Err(e) =
+            finish_blanks(&args, mode, &mut state, &mut section).and_then(|_| section.flush())
+        {
+            warn(&mut out, format_args!("write error: {}", error_message(&e)));
+            failed = true;
+        }
+    }
+
+    // The closure takes `out` by value, so it has been dropped, and flushed, by the time the
+    // error is printed, whichever step failed.
+    if let Err(e) = finish_blanks(&args, mode, &mut state, &mut out)
+        .and_then(|_| finish_output(&args, &mut state, out))
+    {
+        eprintln!("cat: write error: {}", error_message(&e));
+        failed = true;
+    }
+
+    // The report goes to stderr, so it never mixes with the output.
+    if let Some(summary) = &state.summary {
+        let summary = summary.borrow();
+        eprintln!(
+            "cat: summary: bytes={} lines={} files={} failed={} squeezed={}",
+            summary.bytes, summary.lines, summary.files, summary.failed, summary.squeezed
+        );
+    }
+
+    if state.binary {
+        ExitCode::from(2)
+    } else if (failed && !args.ignore_errors)
+        || state.too_wide
+        || state.invalid_utf8
+        || state.differs
+    {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_cmd::prelude::*;
+    use std::io::Read;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process::Command;
+    use std::process::Output;
+    use std::process::Stdio;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    // Run cat with the given arguments, feeding the input through stdin.
+    fn run_with_stdin(args: &[&str], input: &[u8]) -> Output {
+        let mut cmd = Command::cargo_bin("cat").unwrap();
+        cmd.args(args);
+        pipe_through(cmd, input)
+    }
+
+    // Run a command, feeding the input through stdin.
+    fn pipe_through(mut cmd: Command, input: &[u8]) -> Output {
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        // Write from a separate thread so a large output cannot block the input.
+        let mut stdin = child.stdin.take().unwrap();
+        let input = input.to_vec();
+        let writer = thread::spawn(move || {
+            // The command may exit before consuming everything (e.g. on a usage error).
+            let _ = stdin.write_all(&input);
+        });
+
+        let output = child.wait_with_output().unwrap();
+        writer.join().unwrap();
+        output
+    }
+
+    // A scratch directory for test files, removed with everything in it when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> TempDir {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            let dir = std::env::temp_dir().join(format!(
+                "cat-test-{}-{}",
+                std::process::id(),
+                COUNT.fetch_add(1, Ordering::Relaxed)
+            ));
+            std::fs::create_dir(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        // The path of a file in the directory, which need not exist.
+        fn path(&self, name: &str) -> String {
+            self.0.join(name).into_os_string().into_string().unwrap()
+        }
+
+        // Write a file into the directory and return its path.
+        fn file(&self, name: &str, content: impl AsRef<[u8]>) -> String {
+            let path = self.path(name);
+            std::fs::write(&path, content).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // Find a GNU cat on the PATH to compare against.
+    fn system_gnu_cat() -> Option<PathBuf> {
+        let this_cat = Command::cargo_bin("cat").unwrap().get_program().to_owned();
+        let system_cat = std::env::split_paths(&std::env::var_os("PATH")?)
+            .map(|dir| dir.join("cat"))
+            .find(|path| path.is_file() && path.as_os_str() != this_cat)?;
+        let version = Command::new(&system_cat).arg("--version").output().ok()?;
+
+        String::from_utf8_lossy(&version.stdout)
+            .contains("GNU coreutils")
+            .then_some(system_cat)
+    }
+
+    // Test cat of a single file without formatting.
+    #[test]
+    fn test_cat_no_formatting() {
+        let mut cmd = Command::cargo_bin("cat").unwrap();
+        let mut test_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_path.push("tests/test.txt");
+        let expected_output: Vec<u8> = vec![
+            116, 101, 115, 116, 9, 9, 10, 10, 10, 10, 9, 9, 116, 101, 115, 116, 10, 116, 101, 115,
+            116, 10, 0, 1, 2, 3, 10, 127, 10, 128, 129, 10, 160, 161, 10, 255, 10,
+        ];
+
+        let output = cmd
+            .arg(test_path.into_os_string().into_string().unwrap())
+            .output()
+            .unwrap();
+        assert_eq!(output.stdout, expected_output);
+    }
+
+    // Test cat of a single file with different formatting options.
+    #[test]
+    fn test_cat_with_formatting() {
+        let mut cmd = Command::cargo_bin("cat").unwrap();
+        let mut test_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_path.push("tests/test.txt");
+        let test_string = test_path.into_os_string().into_string().unwrap();
+        let mut expected_output: Vec<u8> = vec![
+            49, 32, 116, 101, 115, 116, 94, 73, 94, 73, 36, 10, 50, 32, 36, 10, 51, 32, 94, 73, 94,
+            73, 116, 101, 115, 116, 36, 10, 52, 32, 116, 101, 115, 116, 36, 10, 53, 32, 94, 64, 94,
+            65, 94, 66, 94, 67, 36, 10, 54, 32, 94, 63, 36, 10, 55, 32, 77, 45, 94, 64, 77, 45, 94,
+            65, 36, 10, 56, 32, 77, 45, 32, 77, 45, 33, 36, 10, 57, 32, 77, 45, 94, 63, 36, 10,
+        ];
+
+        // Show all, squeeze blanks and show all numbers.
+        let mut output = cmd.arg("-Asn").arg(test_string.clone()).output().unwrap();
+        assert_eq!(output.stdout, expected_output);
+
+        // Verify -b option overrides -n.
+        cmd = Command::cargo_bin("cat").unwrap();
+        expected_output = vec![
+            49, 32, 116, 101, 115, 116, 94, 73, 94, 73, 36, 10, 36, 10, 50, 32, 94, 73, 94, 73,
+            116, 101, 115, 116, 36, 10, 51, 32, 116, 101, 115, 116, 36, 10, 52, 32, 94, 64, 94, 65,
+            94, 66, 94, 67, 36, 10, 53, 32, 94, 63, 36, 10, 54, 32, 77, 45, 94, 64, 77, 45, 94, 65,
+            36, 10, 55, 32, 77, 45, 32, 77, 45, 33, 36, 10, 56, 32, 77, 45, 94, 63, 36, 10,
+        ];
+        output = cmd.arg("-Asnb").arg(test_string.clone()).output().unwrap();
+        assert_eq!(output.stdout, expected_output);
+    }
+
+    // Test cat of multiple files and stdin.
+    #[test]
+    fn test_cat_multiple() {
+        let mut cmd = Command::cargo_bin("cat").unwrap();
+        let mut test_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_path.push("tests/test.txt");
+        let test_string = test_path.into_os_string().into_string().unwrap();
+        let expected_output: Vec<u8> = vec![
+            49, 32, 116, 101, 115, 116, 94, 73, 94, 73, 36, 10, 50, 32, 36, 10, 51, 32, 94, 73, 94,
+            73, 116, 101, 115, 116, 36, 10, 52, 32, 116, 101, 115, 116, 36, 10, 53, 32, 94, 64, 94,
+            65, 94, 66, 94, 67, 36, 10, 54, 32, 94, 63, 36, 10, 55, 32, 77, 45, 94, 64, 77, 45, 94,
+            65, 36, 10, 56, 32, 77, 45, 32, 77, 45, 33, 36, 10, 57, 32, 77, 45, 94, 63, 36, 10, 49,
+            48, 32, 116, 101, 115, 116, 36, 10, 49, 49, 32, 116, 101, 115, 116, 94, 73, 94, 73, 36,
+            10, 49, 50, 32, 36, 10, 49, 51, 32, 94, 73, 94, 73, 116, 101, 115, 116, 36, 10, 49, 52,
+            32, 116, 101, 115, 116, 36, 10, 49, 53, 32, 94, 64, 94, 65, 94, 66, 94, 67, 36, 10, 49,
+            54, 32, 94, 63, 36, 10, 49, 55, 32, 77, 45, 94, 64, 77, 45, 94, 65, 36, 10, 49, 56, 32,
+            77, 45, 32, 77, 45, 33, 36, 10, 49, 57, 32, 77, 45, 94, 63, 36, 10,
+        ];
+
+        // File, stdin, file.
+        let mut child = cmd
+            .arg("-Asn")
+            .arg(test_string.clone())
+            .arg("-")
+            .arg(test_string.clone())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        // Collect stdin, send a message and drop to close.
+        let stdin = child.stdin.as_mut().unwrap();
+        stdin.write_all(b"test\n").unwrap();
+
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(output.stdout, expected_output);
+    }
+
+    // Test that missing files are reported and --ignore-errors forces exit status 0.
+    #[test]
+    fn test_cat_ignore_errors() {
+        let mut test_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_path.push("tests/test.txt");
+        let test_string = test_path.into_os_string().into_string().unwrap();
+
+        // Without the flag the remaining files are still printed but the status is 1.
+        let mut cmd = Command::cargo_bin("cat").unwrap();
+        let mut output = cmd
+            .arg("missing.txt")
+            .arg(test_string.clone())
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(output.stdout, std::fs::read(&test_string).unwrap());
+        assert_eq!(
+            output.stderr,
+            b"cat: missing.txt: No such file or directory\n"
+        );
+
+        cmd = Command::cargo_bin("cat").unwrap();
+        output = cmd
+            .arg("--ignore-errors")
+            .arg("missing.txt")
+            .arg(test_string.clone())
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(output.stdout, std::fs::read(&test_string).unwrap());
+        assert_eq!(
+            output.stderr,
+            b"cat: missing.txt: No such file or directory\n"
+        );
+    }
+
+    // Test that --wrap-nonprinting never splits an escape sequence across rows.
+    #[test]
+    fn test_cat_wrap_nonprinting() {
+        let output = run_with_stdin(
+            &["-v", "--wrap-nonprinting=5"],
+            b"ab\x01\x7f\xff\xa0x\n\x01\n",
+        );
+        assert_eq!(output.stdout, b"ab^A\n^?\nM-^?\nM- x\n^A\n".to_vec());
+    }
+
+    // Test that --dedent strips the common indentation and empties blank lines.
+    #[test]
+    fn test_cat_dedent() {
+        let output = run_with_stdin(
+            &["--dedent", "-n"],
+            b"    fn main() {\n        run();\n  \t \n    }\n",
+        );
+        assert_eq!(
+            output.stdout,
+            b"1 fn main() {\n2     run();\n3 \n4 }\n".to_vec()
+        );
+    }
+
+    // Test reversing lines byte by byte and character by character.
+    #[test]
+    fn test_cat_reverse() {
+        let input = "caf\u{e9}\n\nabc".as_bytes();
+
+        let mut output = run_with_stdin(&["--reverse-bytes", "-n"], input);
+        assert_eq!(output.stdout, b"1 \xa9\xc3fac\n2 \n3 cba".to_vec());
+
+        output = run_with_stdin(&["--reverse-chars", "-n"], input);
+        assert_eq!(output.stdout, "1 \u{e9}fac\n2 \n3 cba".as_bytes().to_vec());
+    }
+
+    // Test that --formfeed-blank lets -s squeeze form-feed-only lines.
+    #[test]
+    fn test_cat_formfeed_blank() {
+        let input = b"a\n\n\x0c\n\x0c\n\n\nb\n";
+
+        let mut output = run_with_stdin(&["-s"], input);
+        assert_eq!(output.stdout, b"a\n\n\x0c\n\x0c\n\nb\n".to_vec());
+
+        output = run_with_stdin(&["-s", "--formfeed-blank"], input);
+        assert_eq!(output.stdout, b"a\n\nb\n".to_vec());
+    }
+
+    // Test that --crc-lines checksums the raw line, in either position.
+    #[test]
+    fn test_cat_crc_lines() {
+        let input = b"hello\nworld\n\n";
+
+        let mut output = run_with_stdin(&["--crc-lines", "-n"], input);
+        assert_eq!(
+            output.stdout,
+            b"1 3610a686 hello\n2 3a771143 world\n3 00000000 \n".to_vec()
+        );
+
+        // The checksum covers the raw bytes, not the -E/-T output.
+        output = run_with_stdin(&["--crc-lines", "--crc-position=suffix", "-E"], input);
+        assert_eq!(
+            output.stdout,
+            b"hello$ 3610a686\nworld$ 3a771143\n$ 00000000\n".to_vec()
+        );
+
+        // Nor does it cover the line with a comment stripped.
+        output = run_with_stdin(&["--crc-lines", "--strip-comments=hash"], b"x # c\nx\n");
+        assert_eq!(output.stdout, b"d4861fd4 x\n8cdc1683 x\n".to_vec());
+    }
+
+    // Test zero and space padded line numbers.
+    #[test]
+    fn test_cat_number_pad() {
+        let input = b"a\nb\n";
+
+        let mut output = run_with_stdin(&["-n", "--number-pad=zero", "--number-width=4"], input);
+        assert_eq!(output.stdout, b"0001 a\n0002 b\n".to_vec());
+
+        output = run_with_stdin(&["-n", "--number-width=4"], input);
+        assert_eq!(output.stdout, b"   1 a\n   2 b\n".to_vec());
+
+        output = run_with_stdin(&["-n", "--number-pad=zero"], input);
+        assert_eq!(output.stdout, b"000001 a\n000002 b\n".to_vec());
+    }
+
+    // Test that --normalize-blanks squeezes mixed LF and CRLF blank runs.
+    #[test]
+    fn test_cat_normalize_blanks() {
+        let input = b"a\r\n\n\r\n\nb\r\n";
+
+        let mut output = run_with_stdin(&["-s"], input);
+        assert_eq!(output.stdout, b"a\r\n\n\r\n\nb\r\n".to_vec());
+
+        output = run_with_stdin(&["-s", "--normalize-blanks"], input);
+        assert_eq!(output.stdout, b"a\r\n\nb\r\n".to_vec());
+    }
+
+    // Test that --show-line-endings distinguishes CR, LF and CRLF endings.
+    #[test]
+    fn test_cat_show_line_endings() {
+        let input = b"crlf\r\nlf\ncr\rcr\rend";
+
+        let mut output = run_with_stdin(&["--show-line-endings"], input);
+        assert_eq!(output.stdout, b"crlf^M$\nlf$\ncr^Mcr^Mend".to_vec());
+        assert!(output.stderr.is_empty());
+
+        output = run_with_stdin(&["--show-line-endings", "--verbose"], input);
+        assert_eq!(output.stdout, b"crlf^M$\nlf$\ncr^Mcr^Mend".to_vec());
+        assert_eq!(
+            output.stderr,
+            b"cat: line endings: ^M$ = CRLF, $ = LF, ^M = CR\n".to_vec()
+        );
+    }
+
+    // Test that each --profile enables its underlying options.
+    #[test]
+    fn test_cat_profile() {
+        let input = b"a\tb\n\n\n\nc\n";
+
+        let mut output = run_with_stdin(&["--profile=log"], input);
+        assert_eq!(output.stdout, b"a\tb\n\nc\n".to_vec());
+
+        output = run_with_stdin(&["--profile=code"], input);
+        assert_eq!(output.stdout, b"1 a^Ib\n2 \n3 \n4 \n5 c\n".to_vec());
+
+        // Explicit options still combine with a profile.
+        output = run_with_stdin(&["--profile=code", "-b"], input);
+        assert_eq!(output.stdout, b"1 a^Ib\n\n\n\n2 c\n".to_vec());
+    }
+
+    // Test the ASCII case transforms.
+    #[test]
+    fn test_cat_case() {
+        let input = b"Hello, World! 42\n";
+
+        let mut output = run_with_stdin(&["--upper"], input);
+        assert_eq!(output.stdout, b"HELLO, WORLD! 42\n".to_vec());
+
+        output = run_with_stdin(&["--lower"], input);
+        assert_eq!(output.stdout, b"hello, world! 42\n".to_vec());
+
+        output = run_with_stdin(&["--invert-case"], input);
+        assert_eq!(output.stdout, b"hELLO, wORLD! 42\n".to_vec());
+
+        output = run_with_stdin(&["--upper", "--lower"], input);
+        assert!(!output.status.success());
+    }
+
+    // Fuzz random inputs through this cat and GNU cat and compare the outputs, also checking
+    // that --compat-check agrees. Skipped when no GNU cat is installed. Numbering is left out
+    // since this cat prints "N " rather than GNU's right-justified "N\t".
+    #[test]
+    fn test_cat_compat_fuzz() {
+        let Some(system_cat) = system_gnu_cat() else {
+            return;
+        };
+        let flags = [
+            "-u", "-v", "-E", "-T", "-s", "-A", "-e", "-t", "-vs", "-ET", "-As", "-sT",
+        ];
+        let alphabet = [b'\n', b'\n', b'\n', b'\t', b'\r', b' ', b'a'];
+
+        // Xorshift, so failures are reproducible without a rand dependency.
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..200 {
+            let len = next() % 64;
+            let input: Vec<u8> = (0..len)
+                .map(|_| match next() % 2 {
+                    0 => alphabet[(next() % alphabet.len() as u64) as usize],
+                    _ => next() as u8,
+                })
+                .collect();
+            let flag = flags[(next() % flags.len() as u64) as usize];
+
+            let ours = run_with_stdin(&[flag, "--compat-check"], &input);
+            let mut cmd = Command::new(&system_cat);
+            cmd.arg(flag);
+            let theirs = pipe_through(cmd, &input);
+
+            assert_eq!(ours.stdout, theirs.stdout, "cat {} on {:?}", flag, input);
+            assert!(ours.stderr.is_empty(), "cat {} on {:?}", flag, input);
+        }
+    }
+
+    // Test --expand-env with set and unset variables.
+    #[test]
+    fn test_cat_expand_env() {
+        let input = b"user=${CAT_TEST_USER} unset=${CAT_TEST_UNSET} cost=$$5 $HOME ${\n";
+
+        let mut cmd = Command::cargo_bin("cat").unwrap();
+        cmd.args(["--expand-env", "-n"])
+            .env("CAT_TEST_USER", "root")
+            .env_remove("CAT_TEST_UNSET");
+        let mut output = pipe_through(cmd, input);
+        assert_eq!(
+            output.stdout,
+            b"1 user=root unset= cost=$5 $HOME ${\n".to_vec()
+        );
+
+        cmd = Command::cargo_bin("cat").unwrap();
+        cmd.args(["--expand-env", "--strict-env"])
+            .env("CAT_TEST_USER", "root")
+            .env_remove("CAT_TEST_UNSET");
+        output = pipe_through(cmd, input);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(
+            output.stderr,
+            b"cat: -: CAT_TEST_UNSET: environment variable not set\n".to_vec()
+        );
+    }
+
+    // Test that --squeeze-whitespace-lines collapses space and tab only runs.
+    #[test]
+    fn test_cat_squeeze_whitespace_lines() {
+        let input = b"a\n   \n\t\n \t \n\nb\n\t\nc\n";
+
+        let mut output = run_with_stdin(&["-s"], input);
+        assert_eq!(output.stdout, input.to_vec());
+
+        output = run_with_stdin(&["--squeeze-whitespace-lines"], input);
+        assert_eq!(output.stdout, b"a\n\nb\n\nc\n".to_vec());
+
+        // --crc-lines still checksums the whitespace that was collapsed.
+        output = run_with_stdin(&["--squeeze-whitespace-lines", "--crc-lines"], b"  \n");
+        assert_eq!(output.stdout, b"ef331695 \n".to_vec());
+    }
+
+    // Test wrapping lines with --line-prefix and --line-suffix.
+    #[test]
+    fn test_cat_line_prefix_suffix() {
+        let input = b"first\n\nlast";
+
+        let mut output = run_with_stdin(&["--line-prefix", "> "], input);
+        assert_eq!(output.stdout, b"> first\n> \n> last".to_vec());
+
+        // The prefix goes before the number and the suffix before the newline.
+        output = run_with_stdin(
+            &["-nE", "--line-prefix=\\t|", "--line-suffix", " \\\\"],
+            input,
+        );
+        assert_eq!(
+            output.stdout,
+            b"\t|1 first$ \\\n\t|2 $ \\\n\t|3 last \\".to_vec()
+        );
+    }
+
+    // Test that --sample keeps every Nth line and numbers reflect original positions.
+    #[test]
+    fn test_cat_sample() {
+        let input = b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+
+        let mut output = run_with_stdin(&["--sample", "3"], input);
+        assert_eq!(output.stdout, b"3\n6\n9\n".to_vec());
+
+        output = run_with_stdin(&["--sample", "3", "--sample-offset", "2", "-n"], input);
+        assert_eq!(output.stdout, b"2 2\n5 5\n8 8\n".to_vec());
+
+        output = run_with_stdin(&["--sample", "0"], input);
+        assert!(!output.status.success());
+    }
+
+    // Test that --show-tabs-aligned keeps the text after each tab on its tab stop.
+    #[test]
+    fn test_cat_show_tabs_aligned() {
+        let output = run_with_stdin(
+            &["--show-tabs-aligned"],
+            b"\tx\nabc\tx\nabcdef\tx\nabcdefg\tx\n\t\tx\n",
+        );
+        assert_eq!(
+            output.stdout,
+            b"^I      x\nabc^I   x\nabcdef^Ix\nabcdefg^I       x\n^I      ^I      x\n".to_vec()
+        );
+    }
+
+    // Test --head-bytes and --tail-bytes over the concatenated input.
+    #[test]
+    fn test_cat_head_tail_bytes() {
+        let mut test_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_path.push("tests/test.txt");
+        let test_string = test_path.into_os_string().into_string().unwrap();
+        let content = std::fs::read(&test_string).unwrap();
+
+        let mut output = run_with_stdin(&["--head-bytes", "7", "-n", &test_string], b"");
+        assert_eq!(output.stdout, content[..7].to_vec());
+
+        // The limits apply to the whole stream, across files.
+        output = run_with_stdin(&["--head-bytes", "40", &test_string, "-"], b"stdin\n");
+        assert_eq!(output.stdout, [&content[..], b"std"].concat());
+
+        output = run_with_stdin(&["--tail-bytes", "8", &test_string, "-"], b"stdin\n");
+        assert_eq!(
+            output.stdout,
+            [&content[content.len() - 2..], b"stdin\n"].concat()
+        );
+
+        // Limits larger than the input print everything.
+        output = run_with_stdin(&["--tail-bytes", "1000", &test_string], b"");
+        assert_eq!(output.stdout, content);
+    }
+
+    // Test every combination of -n/-b with and without -s, matching GNU cat's numbering rules.
+    #[test]
+    fn test_cat_number_squeeze_matrix() {
+        let input = b"a\n\n\n\nb\n\nc\n";
+
+        for (flags, expected_output) in [
+            ("-n", &b"1 a\n2 \n3 \n4 \n5 b\n6 \n7 c\n"[..]),
+            ("-ns", b"1 a\n2 \n3 b\n4 \n5 c\n"),
+            ("-b", b"1 a\n\n\n\n2 b\n\n3 c\n"),
+            ("-bs", b"1 a\n\n2 b\n\n3 c\n"),
+            ("-nb", b"1 a\n\n\n\n2 b\n\n3 c\n"),
+            ("-nbs", b"1 a\n\n2 b\n\n3 c\n"),
+        ] {
+            let output = run_with_stdin(&[flags], input);
+            assert_eq!(output.stdout, expected_output.to_vec(), "cat {}", flags);
+        }
+    }
+
+    // Test that --highlight-syntax leaves piped output plain, with or without the feature.
+    #[test]
+    fn test_cat_highlight_syntax_piped() {
+        let mut source_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        source_path.push("src/main.rs");
+        let source_string = source_path.into_os_string().into_string().unwrap();
+
+        let output = run_with_stdin(&["--highlight-syntax", &source_string], b"");
+        assert_eq!(output.stdout, std::fs::read(&source_string).unwrap());
+    }
+
+    // Test that highlighting colors the finished content, after --classify and --truncate have
+    // looked at the plain line. A stand-in highlighter is used, as the real one needs a terminal.
+    #[test]
+    fn test_format_line_highlight() {
+        use super::{content_len, format_line, resolve_args, Args};
+        use clap::Parser;
+
+        let mut bold = |line: &mut Vec<u8>| {
+            let end = content_len(line);
+            line.splice(end..end, *b"\x1b[0m");
+            line.splice(0..0, *b"\x1b[1m");
+        };
+
+        for (flags, expected_output) in [
+            (&["--classify"][..], &b"[text] \x1b[1mabcdef\x1b[0m\n"[..]),
+            (&["--truncate=4"], b"\x1b[1mabc\xe2\x80\xa6\x1b[0m\n"),
+        ] {
+            let mut args = Args::parse_from(std::iter::once("cat").chain(flags.iter().copied()));
+            resolve_args(&mut args);
+
+            let mut line = b"abcdef\n".to_vec();
+            let (mut line_number, mut newlines) = (1, 0);
+            format_line(
+                &mut line,
+                &args,
+                &mut line_number,
+                &mut newlines,
+                0,
+                None,
+                Some(&mut bold),
+            )
+            .unwrap();
+            assert_eq!(line, expected_output.to_vec(), "cat {:?}", flags);
+        }
+    }
+
+    // Test literal and regex --replace pairs, applied in order.
+    #[test]
+    fn test_cat_replace() {
+        let input = b"aaa ab $1\n";
+
+        // Matches do not overlap and each pair sees the output of the previous one.
+        let mut output = run_with_stdin(
+            &[
+                "--replace",
+                "aa",
+                "b",
+                "--replace",
+                "b",
+                "c",
+                "--replace",
+                "$1",
+                "$2",
+            ],
+            input,
+        );
+        assert_eq!(output.stdout, b"ca ac $2\n".to_vec());
+
+        output = run_with_stdin(&["--replace-regex", "--replace", "a+", "<$0>", "-n"], input);
+        assert_eq!(output.stdout, b"1 <aaa> <a>b $1\n".to_vec());
+
+        output = run_with_stdin(&["--replace-regex", "--replace", "(", "x"], input);
+        assert_eq!(output.status.code(), Some(2));
+    }
+
+    // Test that --read-timeout gives up on a FIFO that never receives data.
+    #[cfg(unix)]
+    #[test]
+    fn test_cat_read_timeout() {
+        let mut test_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_path.push("tests/test.txt");
+        let test_string = test_path.into_os_string().into_string().unwrap();
+        let dir = TempDir::new();
+        let fifo_string = dir.path("fifo");
+        let fifo_c = std::ffi::CString::new(fifo_string.clone()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo_c.as_ptr(), 0o600) }, 0);
+
+        let output = assert_cmd::Command::cargo_bin("cat")
+            .unwrap()
+            .args(["--read-timeout", "200", &fifo_string, &test_string])
+            .timeout(std::time::Duration::from_secs(10))
+            .output()
+            .unwrap();
+
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(output.stdout, std::fs::read(&test_string).unwrap());
+        assert_eq!(
+            output.stderr,
+            format!("cat: {}: read timed out\n", fifo_string).into_bytes()
+        );
+    }
+
+    // Build a blank-heavy input for comparing the -s fast path with the general path.
+    fn squeeze_input() -> Vec<u8> {
+        let mut input = Vec::new();
+        for i in 0..20000 {
+            input.resize(input.len() + i % 7, b'\n');
+            input.extend_from_slice(format!("line {}\n", i).as_bytes());
+        }
+        input
+    }
+
+    // Test that the -s fast path matches the general formatting path on a blank-heavy input.
+    // An empty --line-prefix forces the general path.
+    #[test]
+    fn test_cat_squeeze_fast_path() {
+        let input = squeeze_input();
+
+        let fast = run_with_stdin(&["-s"], &input);
+        let general = run_with_stdin(&["-s", "--line-prefix="], &input);
+        assert_eq!(fast.stdout, general.stdout);
+    }
+
+    // Benchmark the -s fast path against the general path, taking the best of several runs.
+    // Wall-clock timings are too noisy for CI, so run it on demand with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn bench_squeeze_fast_path() {
+        let input = squeeze_input();
+        let best = |args: &[&str]| {
+            (0..5)
+                .map(|_| {
+                    let start = std::time::Instant::now();
+                    run_with_stdin(args, &input);
+                    start.elapsed()
+                })
+                .min()
+                .unwrap()
+        };
+
+        let fast_time = best(&["-s"]);
+        let general_time = best(&["-s", "--line-prefix="]);
+        println!(
+            "-s fast path {:?}, general path {:?}",
+            fast_time, general_time
+        );
+        assert!(
+            fast_time <= general_time * 2,
+            "-s fast path took {:?}, general path {:?}",
+            fast_time,
+            general_time
+        );
+    }
+
+    // Test that --start-number sets the first line number, continuing across files.
+    #[test]
+    fn test_cat_start_number() {
+        let mut output = run_with_stdin(&["-n", "--start-number", "100", "-", "-"], b"a\n\nb\n");
+        assert_eq!(output.stdout, b"100 a\n101 \n102 b\n".to_vec());
+
+        output = run_with_stdin(&["-b", "--start-number=100"], b"a\n\nb\n");
+        assert_eq!(output.stdout, b"100 a\n\n101 b\n".to_vec());
+    }
+
+    // Test that --warn-no-newline reports inputs missing a trailing newline.
+    #[test]
+    fn test_cat_warn_no_newline() {
+        let mut output = run_with_stdin(&["--warn-no-newline"], b"a\nb");
+        assert_eq!(output.stdout, b"a\nb".to_vec());
+        assert_eq!(
+            output.stderr,
+            b"cat: -: no newline at end of file\n".to_vec()
+        );
+
+        output = run_with_stdin(&["--warn-no-newline", "-n"], b"a\nb\n");
+        assert_eq!(output.stdout, b"1 a\n2 b\n".to_vec());
+        assert!(output.stderr.is_empty());
+
+        // Empty inputs have nothing to warn about.
+        output = run_with_stdin(&["--warn-no-newline"], b"");
+        assert!(output.stderr.is_empty());
+    }
+
+    // Test both --byte-histogram layouts over a known byte distribution.
+    #[test]
+    fn test_cat_byte_histogram() {
+        let input = b"aab\t\n\x00\xff\n";
+
+        let mut output = run_with_stdin(&["--byte-histogram"], input);
+        assert_eq!(
+            output.stdout,
+            b"0x00 ^@   1\n0x09 ^I   1\n0x0a ^J   2\n0x61 a    2\n0x62 b    1\n0xff M-^? 1\n"
+                .to_vec()
+        );
+
+        output = run_with_stdin(&["--byte-histogram=summary", "-", "-"], input);
+        assert_eq!(
+            output.stdout,
+            b"printable 3\ncontrol 4\nnon-ASCII 1\n".to_vec()
+        );
+    }
+
+    // Test that --squeeze-spaces collapses runs of spaces, or of the chosen byte.
+    #[test]
+    fn test_cat_squeeze_spaces() {
+        let input = b"a   b  c d    \n--x--\n";
+
+        let mut output = run_with_stdin(&["--squeeze-spaces", "-n"], input);
+        assert_eq!(output.stdout, b"1 a b c d \n2 --x--\n".to_vec());
+
+        output = run_with_stdin(&["--squeeze-spaces", "--squeeze-char", "-"], input);
+        assert_eq!(output.stdout, b"a   b  c d    \n-x-\n".to_vec());
+
+        output = run_with_stdin(&["--squeeze-spaces", "--squeeze-char", "ab"], input);
+        assert_eq!(output.status.code(), Some(2));
+    }
+
+    // Test that --compress produces streams that decompress to the formatted output.
+    #[test]
+    fn test_cat_compress() {
+        let input = b"a\tb\n\n\n\nc\n".repeat(100);
+        let plain = run_with_stdin(&["-sT"], &input);
+
+        let mut output = run_with_stdin(&["-sT", "--compress=gzip"], &input);
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(output.stdout.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, plain.stdout);
+
+        output = run_with_stdin(&["-sT", "--compress=zstd"], &input);
+        decompressed = zstd::decode_all(output.stdout.as_slice()).unwrap();
+        assert_eq!(decompressed, plain.stdout);
+    }
+
+    // Test that --with-filename prefixes each line with its source, before the number.
+    #[test]
+    fn test_cat_with_filename() {
+        let mut test_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_path.push("tests/test.txt");
+        let test_string = test_path.into_os_string().into_string().unwrap();
+
+        let mut output = run_with_stdin(&["--with-filename", "-n", "-", &test_string], b"a\n");
+        let mut expected_output = b"(standard input):1 a\n".to_vec();
+        for (idx, line) in std::fs::read(&test_string)
+            .unwrap()
+            .split_inclusive(|&c| c == b'\n')
+            .enumerate()
+        {
+            expected_output.extend_from_slice(format!("{}:{} ", test_string, idx + 2).as_bytes());
+            expected_output.extend_from_slice(line);
+        }
+        assert_eq!(output.stdout, expected_output);
+
+        output = run_with_stdin(&["-H", "--no-filename", "-", "-"], b"a\n");
+        assert_eq!(output.stdout, b"a\n".to_vec());
+
+        // The name comes first and, like the rest of the line, goes through --translate.
+        output = run_with_stdin(
+            &["-H", "--line-prefix=> ", "--translate", "a-z", "A-Z", "-"],
+            b"a\n",
+        );
+        assert_eq!(output.stdout, b"(STANDARD INPUT):> A\n".to_vec());
+
+        // A last line without a newline is continued by the next file, under its own name.
+        let dir = TempDir::new();
+        let first = dir.file("first", b"a");
+        output = run_with_stdin(&["-H", &first, "-", &first], b"b\n");
+        assert_eq!(output.stdout, format!("{0}:ab\n{0}:a", first).into_bytes());
+    }
+
+    // Test that --trim-blank-runs-to only trims blank runs at file boundaries.
+    #[test]
+    fn test_cat_trim_blank_runs_to() {
+        let dir = TempDir::new();
+        let first_string = dir.file("first", b"\n\na\n\n\n\nb\n\n\n");
+        let second_string = dir.file("second", b"\n\nc\n\n\n\n");
+
+        let output = run_with_stdin(
+            &[
+                "--trim-blank-runs-to",
+                "1",
+                "-n",
+                &first_string,
+                &second_string,
+            ],
+            b"",
+        );
+
+        // The four blanks around the seam become one; runs inside a file and at the very start
+        // and end of the output are kept.
+        assert_eq!(
+            output.stdout,
+            b"1 \n2 \n3 a\n4 \n5 \n6 \n7 b\n8 \n9 c\n10 \n11 \n12 \n".to_vec()
+        );
+    }
+
+    // Test that --read-whole produces the same output as streaming.
+    #[test]
+    fn test_cat_read_whole() {
+        let mut test_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_path.push("tests/test.txt");
+        let test_string = test_path.into_os_string().into_string().unwrap();
+
+        let streamed = run_with_stdin(&["-Asn", &test_string, "-", &test_string], b"a\n\n\n");
+        let whole = run_with_stdin(
+            &["--read-whole", "-Asn", &test_string, "-", &test_string],
+            b"a\n\n\n",
+        );
+        assert_eq!(whole.stdout, streamed.stdout);
+    }
+
+    // Test that --raw copies bytes exactly and refuses formatting options.
+    #[test]
+    fn test_cat_raw() {
+        let input = b"a\r\n\n\n\tb\x00\xff";
+
+        let mut output = run_with_stdin(&["--raw"], input);
+        assert_eq!(output.stdout, input.to_vec());
+
+        output = run_with_stdin(&["--raw", "-n"], input);
+        assert_eq!(output.status.code(), Some(2));
+        assert!(output.stdout.is_empty());
+
+        output = run_with_stdin(&["--raw", "--profile=log"], input);
+        assert_eq!(output.status.code(), Some(2));
+    }
+
+    // Test that --squeeze-min-bytes leaves small files unsqueezed.
+    #[test]
+    fn test_cat_squeeze_min_bytes() {
+        let dir = TempDir::new();
+        let small_string = dir.file("small", b"a\n\n\nb\n");
+        let large_string = dir.file("large", b"long line\n\n\n\nb\n");
+
+        let mut output = run_with_stdin(
+            &[
+                "-s",
+                "--squeeze-min-bytes",
+                "8",
+                &small_string,
+                &large_string,
+            ],
+            b"",
+        );
+        assert_eq!(output.stdout, b"a\n\n\nb\nlong line\n\nb\n".to_vec());
+
+        output = run_with_stdin(
+            &["-sn", "--squeeze-min-bytes=8", &small_string, &large_string],
+            b"",
+        );
+        assert_eq!(
+            output.stdout,
+            b"1 a\n2 \n3 \n4 b\n5 long line\n6 \n7 b\n".to_vec()
+        );
+    }
+
+    // Test that diagnostics stay in order with the output when both share a pipe.
+    #[test]
+    fn test_cat_diagnostics_order() {
+        let (mut reader, writer) = std::io::pipe().unwrap();
+        let mut child = Command::cargo_bin("cat")
+            .unwrap()
+            .args(["--warn-no-newline", "-", "missing.txt", "-"])
+            .stdin(Stdio::piped())
+            .stdout(writer.try_clone().unwrap())
+            .stderr(writer)
+            .spawn()
+            .unwrap();
+
+        // Collect stdin, send a message and drop to close.
+        let stdin = child.stdin.as_mut().unwrap();
+        stdin.write_all(b"a\nb").unwrap();
+        drop(child.stdin.take());
+        child.wait().unwrap();
+
+        let mut merged = Vec::new();
+        reader.read_to_end(&mut merged).unwrap();
+        assert_eq!(
+            merged,
+            b"a\nbcat: -: no newline at end of file\n\
+              cat: missing.txt: No such file or directory\n"
+                .to_vec()
+        );
+    }
+
+    // Test every blank-line rule on its own and combined.
+    #[test]
+    fn test_is_blank_line() {
+        use super::{is_blank_line, Args};
+        use clap::Parser;
+
+        let plain = Args::parse_from(["cat"]);
+        assert!(is_blank_line(b"\n", &plain));
+        assert!(!is_blank_line(b"", &plain));
+        assert!(!is_blank_line(b"a\n", &plain));
+        assert!(!is_blank_line(b"\r\n", &plain));
+        assert!(!is_blank_line(b"\x0c\n", &plain));
+        assert!(!is_blank_line(b" \t\n", &plain));
+
+        let crlf = Args::parse_from(["cat", "--normalize-blanks"]);
+        assert!(is_blank_line(b"\r\n", &crlf));
+        assert!(!is_blank_line(b"\r", &crlf));
+        assert!(!is_blank_line(b"\r\r\n", &crlf));
+
+        let formfeed = Args::parse_from(["cat", "--formfeed-blank"]);
+        assert!(is_blank_line(b"\x0c\n", &formfeed));
+        assert!(!is_blank_line(b"\x0c\x0c\n", &formfeed));
+        assert!(!is_blank_line(b"\r\n", &formfeed));
+
+        let whitespace = Args::parse_from(["cat", "--squeeze-whitespace-lines"]);
+        assert!(is_blank_line(b" \t \n", &whitespace));
+        assert!(!is_blank_line(b" \t ", &whitespace));
+        assert!(!is_blank_line(b" x\n", &whitespace));
+        assert!(!is_blank_line(b"\x0c\n", &whitespace));
+
+        let all = Args::parse_from([
+            "cat",
+            "--normalize-blanks",
+            "--formfeed-blank",
+            "--squeeze-whitespace-lines",
+        ]);
+        for line in [&b"\n"[..], b"\r\n", b"\x0c\n", b"  \n"] {
+            assert!(is_blank_line(line, &all));
+        }
+        assert!(!is_blank_line(b" \r\n", &all));
+    }
+
+    // Test that --limit-files stops after N inputs and warns about the rest.
+    #[test]
+    fn test_cat_limit_files() {
+        let dir = TempDir::new();
+        let files: Vec<String> = (1..=5)
+            .map(|i| dir.file(&i.to_string(), format!("file {}\n", i)))
+            .collect();
+
+        let output = Command::cargo_bin("cat")
+            .unwrap()
+            .arg("--limit-files")
+            .arg("2")
+            .args(&files)
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"file 1\nfile 2\n".to_vec());
+        assert_eq!(
+            output.stderr,
+            b"cat: --limit-files 2: skipped 3 more file(s)\n".to_vec()
+        );
+    }
+
+    // Test that -sb neither numbers nor counts the blank lines it drops.
+    #[test]
+    fn test_cat_squeeze_number_nonblank() {
+        let input = b"\n\n\na\n\n\n\nb\nc\n\n\n";
+
+        let mut output = run_with_stdin(&["-sb"], input);
+        assert_eq!(output.stdout, b"\n1 a\n\n2 b\n3 c\n\n".to_vec());
+
+        output = run_with_stdin(&["-sb", "--number-width=6"], input);
+        assert_eq!(
+            output.stdout,
+            b"\n     1 a\n\n     2 b\n     3 c\n\n".to_vec()
+        );
+    }
+
+    // Test that --detect-encoding transcodes UTF-16 by its BOM and drops a UTF-8 BOM.
+    #[test]
+    fn test_cat_detect_encoding() {
+        let text = "héllo\nwörld\n";
+        let mut little_endian = vec![0xFF, 0xFE];
+        let mut big_endian = vec![0xFE, 0xFF];
+        for unit in text.encode_utf16() {
+            little_endian.extend_from_slice(&unit.to_le_bytes());
+            big_endian.extend_from_slice(&unit.to_be_bytes());
+        }
+
+        let dir = TempDir::new();
+        for (name, content) in [("le", little_endian), ("be", big_endian)] {
+            let path = dir.file(name, content);
+
+            let output = Command::cargo_bin("cat")
+                .unwrap()
+                .args(["--detect-encoding", "-n"])
+                .arg(&path)
+                .output()
+                .unwrap();
+
+            assert_eq!(
+                output.stdout,
+                "1 héllo\n2 wörld\n".as_bytes().to_vec(),
+                "UTF-16{}",
+                name
+            );
+        }
+
+        let mut output = run_with_stdin(&["--detect-encoding"], b"\xEF\xBB\xBFabc\n");
+        assert_eq!(output.stdout, b"abc\n".to_vec());
+
+        output = run_with_stdin(&["--detect-encoding"], b"ab\n\xFF");
+        assert_eq!(output.stdout, b"ab\n\xFF".to_vec());
+    }
+
+    // Test -s on empty, single-blank and all-blank input.
+    #[test]
+    fn test_cat_squeeze_blank_only_input() {
+        // The -s fast path and the formatting path (via -E) must agree.
+        for (input, squeezed, squeezed_ends) in [
+            (&b""[..], &b""[..], &b""[..]),
+            (b"\n", b"\n", b"$\n"),
+            (b"\n\n\n\n", b"\n", b"$\n"),
+        ] {
+            let mut output = run_with_stdin(&["-s"], input);
+            assert_eq!(output.stdout, squeezed.to_vec(), "cat -s {:?}", input);
+
+            output = run_with_stdin(&["-sE"], input);
+            assert_eq!(output.stdout, squeezed_ends.to_vec(), "cat -sE {:?}", input);
+        }
+    }
+
+    // Test that --reflow re-wraps paragraphs and -n numbers the wrapped lines.
+    #[test]
+    fn test_cat_reflow() {
+        let input = b"The quick brown\nfox jumps over\nthe lazy dog and\nkeeps running\nuntil the end of the day.\n\nA second\nparagraph.\n";
+
+        let mut output = run_with_stdin(&["--reflow", "40"], input);
+        assert_eq!(
+            output.stdout,
+            b"The quick brown fox jumps over the lazy\n\
+              dog and keeps running until the end of\n\
+              the day.\n\
+              \n\
+              A second paragraph.\n"
+                .to_vec()
+        );
+
+        output = run_with_stdin(&["--reflow", "40", "-n"], input);
+        assert_eq!(
+            output.stdout,
+            b"1 The quick brown fox jumps over the lazy\n\
+              2 dog and keeps running until the end of\n\
+              3 the day.\n\
+              4 \n\
+              5 A second paragraph.\n"
+                .to_vec()
+        );
+
+        // Words longer than the width get a line of their own; no newline is added at EOF.
+        output = run_with_stdin(&["--reflow=5"], b"a abcdefgh b");
+        assert_eq!(output.stdout, b"a\nabcdefgh\nb".to_vec());
+    }
+
+    // Test wrapping the output with --prepend and --append-file.
+    #[test]
+    fn test_cat_prepend_append_file() {
+        let dir = TempDir::new();
+        let header = &dir.file("header", b"<pre>\n");
+        let footer = &dir.file("footer", b"</pre>\n");
+
+        let mut output = run_with_stdin(
+            &["-n", "--prepend", header, "--append-file", footer],
+            b"a\nb\n",
+        );
+        assert_eq!(output.stdout, b"<pre>\n1 a\n2 b\n</pre>\n".to_vec());
+
+        output = run_with_stdin(
+            &[
+                "-n",
+                "--wrap-inside",
+                "--prepend",
+                header,
+                "--append-file",
+                footer,
+            ],
+            b"a\nb\n",
+        );
+        assert_eq!(output.stdout, b"1 <pre>\n2 a\n3 b\n4 </pre>\n".to_vec());
+
+        // --raw promises the input bytes and nothing else.
+        for flag in ["--prepend", "--append-file"] {
+            output = run_with_stdin(&["--raw", flag, header], b"a\n");
+            assert_eq!(output.status.code(), Some(2));
+            assert_eq!(output.stdout, b"".to_vec());
+        }
+
+        output = run_with_stdin(&["--append-file", &dir.path("missing")], b"a\n");
+        assert!(!output.status.success());
+        assert_eq!(output.stdout, b"".to_vec());
+    }
+
+    // Test that --explain lists the options enabled by -A.
+    #[test]
+    fn test_cat_explain() {
+        let output = run_with_stdin(&["--explain", "-A"], b"a\n");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+
+        assert_eq!(output.stdout, b"a$\n".to_vec());
+        assert!(stderr.contains("cat: mode: Format\n"));
+        for option in ["show_ends", "show_tabs", "show_non_printing"] {
+            assert!(
+                stderr.contains(&format!("    {}: true,\n", option)),
+                "{}",
+                option
+            );
+        }
+    }
+
+    // Test that --stdin-name replaces the default label of standard input.
+    #[test]
+    fn test_cat_stdin_name() {
+        let mut output = run_with_stdin(&["-H", "--stdin-name", "request.log"], b"a\nb\n");
+        assert_eq!(output.stdout, b"request.log:a\nrequest.log:b\n".to_vec());
+
+        output = run_with_stdin(&["--stdin-name", "request.log"], b"a\n");
+        assert_eq!(output.stdout, b"a\n".to_vec());
+    }
+
+    // Test each --on-missing policy with a file that does not exist.
+    #[test]
+    fn test_cat_on_missing() {
+        let input = b"\n\na\n";
+
+        let mut output = run_with_stdin(
+            &["-s", "--on-missing=error", "-", "missing.txt", "-"],
+            input,
+        );
+        assert!(!output.status.success());
+        assert_eq!(output.stdout, b"\na\n".to_vec());
+        assert_eq!(
+            output.stderr,
+            b"cat: missing.txt: No such file or directory\n".to_vec()
+        );
+
+        for policy in ["--on-missing=skip", "--on-missing=empty"] {
+            output = run_with_stdin(&["-n", policy, "missing.txt", "-"], input);
+            assert!(output.status.success(), "{}", policy);
+            assert_eq!(output.stdout, b"1 \n2 \n3 a\n".to_vec(), "{}", policy);
+            assert_eq!(output.stderr, b"".to_vec(), "{}", policy);
+        }
+    }
+
+    // Test that line state behaves the same whether the input is piped or a regular file.
+    #[test]
+    fn test_cat_stdin_file_parity() {
+        let input = b"\n\n\na\n\n\n\nb\t\n\r\n\r\n\n\n\nc";
+        let dir = TempDir::new();
+        let path = dir.file("input", input);
+
+        for flags in [
+            &["-s"][..],
+            &["-sn"],
+            &["-sb"],
+            &["-sA"],
+            &["-s", "--normalize-blanks"],
+            &["-s", "--read-whole"],
+            &["-s", "--trim-blank-runs-to=1"],
+            &["-n", "--sample=2"],
+        ] {
+            let piped = run_with_stdin(flags, input);
+            let file = Command::cargo_bin("cat")
+                .unwrap()
+                .args(flags)
+                .arg(&path)
+                .output()
+                .unwrap();
+            assert_eq!(piped.stdout, file.stdout, "cat {:?}", flags);
+        }
+    }
+
+    // Test custom --number-format layouts and the rejection of malformed ones.
+    #[test]
+    fn test_cat_number_format() {
+        let input = b"a\n\nb\n";
+
+        let mut output = run_with_stdin(&["-n", "--number-format", "%05d| "], input);
+        assert_eq!(output.stdout, b"00001| a\n00002| \n00003| b\n".to_vec());
+
+        output = run_with_stdin(&["-b", "--number-format", "[%-3d] 100%% "], input);
+        assert_eq!(output.stdout, b"[1  ] 100% a\n\n[2  ] 100% b\n".to_vec());
+
+        // GNU's own layout.
+        output = run_with_stdin(&["-n", "--number-format", "%6d\\t"], input);
+        assert_eq!(output.stdout, b"     1\ta\n     2\t\n     3\tb\n".to_vec());
+
+        for format in ["line", "%d %d", "%s", "%5"] {
+            output = run_with_stdin(&["-n", "--number-format", format], input);
+            assert_eq!(output.status.code(), Some(2), "{}", format);
+            assert_eq!(output.stdout, b"".to_vec(), "{}", format);
+        }
+    }
+
+    // Test that --ensure-newline-between-files keeps lines of different files apart.
+    #[test]
+    fn test_cat_ensure_newline_between_files() {
+        let dir = TempDir::new();
+        let path = &dir.file("input", b"a\nb");
+
+        let mut output = run_with_stdin(&[path, "-"], b"c\n");
+        assert_eq!(output.stdout, b"a\nbc\n".to_vec());
+
+        output = run_with_stdin(&["--ensure-newline-between-files", path, "-", path], b"c\n");
+        assert_eq!(output.stdout, b"a\nb\nc\na\nb".to_vec());
+
+        output = run_with_stdin(&["--ensure-newline-between-files", "-n", path, path], b"");
+        assert_eq!(output.stdout, b"1 a\n2 b\n3 a\n4 b".to_vec());
+    }
+
+    // Test --hash-check with matching and mismatching digests of the concatenated input.
+    #[test]
+    fn test_cat_hash_check() {
+        let dir = TempDir::new();
+        let path = dir.path("digest");
+        let digest = path.as_str();
+        let input = b"hello\n";
+
+        for expected in [
+            "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03  -\n",
+            "SHA256:5891B5B522D5DF086D0FF0B110FBD9D21BB4FC7163AF34D08286A2E846F6BE03",
+            "crc32:363a3020",
+        ] {
+            std::fs::write(&path, expected).unwrap();
+            let output = run_with_stdin(&["-n", "--hash-check", digest], input);
+            assert!(output.status.success(), "{}", expected);
+            assert_eq!(output.stdout, b"1 hello\n".to_vec(), "{}", expected);
+            assert_eq!(output.stderr, b"".to_vec(), "{}", expected);
+        }
+
+        std::fs::write(&path, "crc32:00000000\n").unwrap();
+        let mut output = run_with_stdin(&["--hash-check", digest], input);
+        assert!(!output.status.success());
+        assert_eq!(output.stdout, input.to_vec());
+        assert_eq!(
+            output.stderr,
+            b"cat: --hash-check: crc32 mismatch: expected 00000000, got 363a3020\n".to_vec()
+        );
+
+        output = run_with_stdin(&["--hash-check", digest, "--quiet"], input);
+        assert!(!output.status.success());
+        assert_eq!(output.stdout, b"".to_vec());
+
+        std::fs::write(&path, "md5:abc\n").unwrap();
+        output = run_with_stdin(&["--hash-check", digest], input);
+        assert!(!output.status.success());
+        assert_eq!(output.stdout, b"".to_vec());
+    }
+
+    // Test that --group-separator splits the --sample output where input lines were skipped.
+    #[test]
+    fn test_cat_group_separator() {
+        let input = b"1\n2\n3\n4\n5\n6\n";
+
+        let mut output = run_with_stdin(&["--sample=2", "--group-separator=--"], input);
+        assert_eq!(output.stdout, b"2\n--\n4\n--\n6\n".to_vec());
+
+        // Adjacent lines form a single group.
+        output = run_with_stdin(
+            &["--sample=1", "--sample-offset=3", "--group-separator=--"],
+            input,
+        );
+        assert_eq!(output.stdout, b"3\n4\n5\n6\n".to_vec());
+
+        output = run_with_stdin(&["-n", "--sample=3", "--group-separator=..."], input);
+        assert_eq!(output.stdout, b"3 3\n...\n6 6\n".to_vec());
+    }
+
+    // Test that --squeeze-tabs collapses tab runs before -T renders them.
+    #[test]
+    fn test_cat_squeeze_tabs() {
+        let input = b"a\t\t\tb  \t\tc\n";
+
+        let mut output = run_with_stdin(&["--squeeze-tabs", "-T"], input);
+        assert_eq!(output.stdout, b"a^Ib  ^Ic\n".to_vec());
+
+        output = run_with_stdin(&["--squeeze-tabs", "--squeeze-spaces", "-T"], input);
+        assert_eq!(output.stdout, b"a^Ib ^Ic\n".to_vec());
+    }
+
+    // Test that --fail-on-binary rejects inputs with NUL bytes with exit status 2.
+    #[test]
+    fn test_cat_fail_on_binary() {
+        let dir = TempDir::new();
+        let path = &dir.file("binary", b"\x7fELF\x02\x01\x00\x00");
+
+        let mut output = run_with_stdin(&["--fail-on-binary"], b"text\n");
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"text\n".to_vec());
+
+        output = run_with_stdin(&["--fail-on-binary", path, "-"], b"text\n");
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(output.stdout, b"text\n".to_vec());
+        assert_eq!(
+            output.stderr,
+            format!("cat: {}: binary file (contains NUL bytes)\n", path).into_bytes()
+        );
+
+        output = run_with_stdin(&["--fail-on-binary", "--ignore-errors", path], b"");
+        assert_eq!(output.status.code(), Some(2));
+    }
+
+    // Test that --control-pictures renders control bytes as Unicode symbols.
+    #[test]
+    fn test_cat_control_pictures() {
+        let input = b"a\x00b\x1b[0m\x07\tc\x7f\xc3\xa9\r\n";
+
+        let mut output = run_with_stdin(&["--control-pictures"], input);
+        assert_eq!(
+            output.stdout,
+            "a\u{2400}b\u{241b}[0m\u{2407}\tc\u{2421}é\u{240d}\n"
+                .as_bytes()
+                .to_vec()
+        );
+
+        output = run_with_stdin(&["--control-pictures", "-T"], b"\x01\t\n");
+        assert_eq!(output.stdout, "\u{2401}^I\n".as_bytes().to_vec());
+
+        for flag in ["-v", "-A", "-e", "-t"] {
+            output = run_with_stdin(&["--control-pictures", flag], b"a\x01\n");
+            assert_eq!(output.status.code(), Some(2));
+            assert_eq!(output.stdout, b"".to_vec());
+        }
+    }
+
+    // Test that --flush-interval delivers trickling input once the interval has passed, without
+    // waiting for the next line, also when the input is polled for --read-timeout.
+    #[test]
+    fn test_cat_flush_interval() {
+        for flags in [
+            &["--flush-interval", "100"][..],
+            &["--flush-interval", "100", "--read-timeout", "10000"],
+        ] {
+            let mut child = Command::cargo_bin("cat")
+                .unwrap()
+                .args(flags)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            let mut stdin = child.stdin.take().unwrap();
+            let mut stdout = child.stdout.take().unwrap();
+
+            // Read on another thread, so a missing flush fails the test instead of hanging it.
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let reader = thread::spawn(move || {
+                let mut buf = [0; 16];
+                while let Ok(n @ 1..) = stdout.read(&mut buf) {
+                    sender.send(buf[..n].to_vec()).unwrap();
+                }
+            });
+            let timeout = std::time::Duration::from_secs(3);
+
+            // The first line must arrive before the second one is written.
+            stdin.write_all(b"a\n").unwrap();
+            assert_eq!(
+                receiver.recv_timeout(timeout),
+                Ok(b"a\n".to_vec()),
+                "cat {:?}",
+                flags
+            );
+
+            stdin.write_all(b"b\n").unwrap();
+            assert_eq!(receiver.recv_timeout(timeout), Ok(b"b\n".to_vec()));
+
+            drop(stdin);
+            assert!(child.wait().unwrap().success());
+            reader.join().unwrap();
+        }
+    }
+
+    // Format each line of the input with format_line directly, as the Format mode would.
+    fn format_lines(flags: &[&str], input: &[u8]) -> Vec<u8> {
+        use super::{format_line, resolve_args, Args};
+        use clap::Parser;
+
+        let mut args = Args::parse_from(std::iter::once("cat").chain(flags.iter().copied()));
+        resolve_args(&mut args);
+
+        let mut line_number = args.start_number;
+        let mut newlines = 0;
+        let mut formatted = Vec::new();
+        for line in input.split_inclusive(|&c| c == b'\n') {
+            let mut line = line.to_vec();
+            format_line(
+                &mut line,
+                &args,
+                &mut line_number,
+                &mut newlines,
+                0,
+                None,
+                None,
+            )
+            .unwrap();
+            formatted.extend_from_slice(&line);
+        }
+        formatted
+    }
+
+    // Test format_line over the numbering, squeezing and display flag matrix.
+    #[test]
+    fn test_format_line() {
+        let input = b"a\n\n\n\tb\x01\n\r\n\n c\x80";
+
+        for (flags, expected_output) in [
+            (&[][..], &input[..]),
+            (&["-n"], b"1 a\n2 \n3 \n4 \tb\x01\n5 \r\n6 \n7  c\x80"),
+            (&["-b"], b"1 a\n\n\n2 \tb\x01\n3 \r\n\n4  c\x80"),
+            (&["-s"], b"a\n\n\tb\x01\n\r\n\n c\x80"),
+            (&["-ns"], b"1 a\n2 \n3 \tb\x01\n4 \r\n5 \n6  c\x80"),
+            (&["-bs"], b"1 a\n\n2 \tb\x01\n3 \r\n\n4  c\x80"),
+            (&["-nb"], b"1 a\n\n\n2 \tb\x01\n3 \r\n\n4  c\x80"),
+            (&["-E"], b"a$\n$\n$\n\tb\x01$\n^M$\n$\n c\x80"),
+            (&["-sE"], b"a$\n$\n\tb\x01$\n^M$\n$\n c\x80"),
+            (&["-T"], b"a\n\n\n^Ib\x01\n\r\n\n c\x80"),
+            (&["-v"], b"a\n\n\n\tb^A\n^M\n\n cM-^@"),
+            (&["-e"], b"a$\n$\n$\n\tb^A$\n^M$\n$\n cM-^@"),
+            (&["-t"], b"a\n\n\n^Ib^A\n^M\n\n cM-^@"),
+            (&["-A"], b"a$\n$\n$\n^Ib^A$\n^M$\n$\n cM-^@"),
+            (&["-nA"], b"1 a$\n2 $\n3 $\n4 ^Ib^A$\n5 ^M$\n6 $\n7  cM-^@"),
+            (&["-sbA"], b"1 a$\n$\n2 ^Ib^A$\n3 ^M$\n$\n4  cM-^@"),
+            (&["-s", "--normalize-blanks"], b"a\n\n\tb\x01\n\r\n c\x80"),
+            (
+                &["-n", "--start-number=-1"],
+                b"-1 a\n0 \n1 \n2 \tb\x01\n3 \r\n4 \n5  c\x80",
+            ),
+        ] {
+            assert_eq!(
+                format_lines(flags, input),
+                expected_output.to_vec(),
+                "cat {:?}",
+                flags
+            );
+        }
+    }
+
+    // Test that format_line only drops blank lines once the squeeze counter is past one.
+    #[test]
+    fn test_format_line_counters() {
+        use super::{format_line, Args};
+        use clap::Parser;
+
+        let args = Args::parse_from(["cat", "-sn"]);
+        let mut line_number = 7;
+        let mut newlines = 1;
+
+        let mut line = b"\n".to_vec();
+        format_line(
+            &mut line,
+            &args,
+            &mut line_number,
+            &mut newlines,
+            0,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(line, b"".to_vec());
+        assert_eq!((line_number, newlines), (7, 2));
+
+        line = b"x\n".to_vec();
+        format_line(
+            &mut line,
+            &args,
+            &mut line_number,
+            &mut newlines,
+            0,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(line, b"7 x\n".to_vec());
+        assert_eq!((line_number, newlines), (8, 0));
+
+        // Neither counter wraps around past the range of 32 bits.
+        line_number = i64::from(i32::MAX);
+        newlines = u64::MAX;
+        line = b"\n".to_vec();
+        format_line(
+            &mut line,
+            &args,
+            &mut line_number,
+            &mut newlines,
+            0,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!((line, newlines), (b"".to_vec(), u64::MAX));
+
+        line = b"x\n".to_vec();
+        format_line(
+            &mut line,
+            &args,
+            &mut line_number,
+            &mut newlines,
+            0,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(line, b"2147483647 x\n".to_vec());
+        assert_eq!(line_number, 2147483648);
+    }
+
+    // Test --strip-comments with the hash and slash styles.
+    #[test]
+    fn test_cat_strip_comments() {
+        let mut output = run_with_stdin(
+            &["--strip-comments=hash", "-n"],
+            b"# header\nkey = 1  # trailing\n  # indented\n\nurl = \"a#b\"\n",
+        );
+        assert_eq!(output.stdout, b"1 key = 1\n2 \n3 url = \"a\n".to_vec());
+
+        output = run_with_stdin(
+            &["--strip-comments=slash"],
+            b"// header\nint x = 1; // trailing\nint y = 2 / 3;\r\nint z; //",
+        );
+        assert_eq!(
+            output.stdout,
+            b"int x = 1;\nint y = 2 / 3;\r\nint z;".to_vec()
+        );
+    }
+
+    // Test that --truncate cuts long lines at 20 columns, with or without the line number.
+    #[test]
+    fn test_cat_truncate() {
+        let input = "short\nthe quick brown fox jumps\nnaïve café au lait crème\n".as_bytes();
+
+        let mut output = run_with_stdin(&["--truncate=20"], input);
+        assert_eq!(
+            output.stdout,
+            "short\nthe quick brown fox…\nnaïve café au lait …\n"
+                .as_bytes()
+                .to_vec()
+        );
+
+        output = run_with_stdin(&["--truncate=20", "--truncate-marker=...", "-n"], input);
+        assert_eq!(
+            output.stdout,
+            b"1 short\n2 the quick brown f...\n3 na\xc3\xafve caf\xc3\xa9 au lai...\n".to_vec()
+        );
+
+        output = run_with_stdin(&["--truncate=20", "--truncate-with-number", "-n"], input);
+        assert_eq!(
+            output.stdout,
+            "1 short\n2 the quick brown f…\n3 naïve café au lai…\n"
+                .as_bytes()
+                .to_vec()
+        );
+
+        // Escape sequences are not split.
+        output = run_with_stdin(
+            &["--truncate=4", "-v", "--truncate-marker=|"],
+            b"ab\x01cd\n",
+        );
+        assert_eq!(output.stdout, b"ab|\n".to_vec());
+
+        // The width must leave room for the marker.
+        output = run_with_stdin(&["--truncate=2", "--truncate-marker=..."], input);
+        assert_eq!(output.status.code(), Some(2));
+        output = run_with_stdin(&["--truncate=0"], input);
+        assert_eq!(output.status.code(), Some(2));
+        output = run_with_stdin(&["--truncate=3", "--truncate-marker=..."], b"abcd\n");
+        assert_eq!(output.stdout, b"...\n".to_vec());
+    }
+
+    // Test that --squeeze-keep picks which blank line of a run is printed.
+    #[test]
+    fn test_cat_squeeze_keep() {
+        let input = b"a\n\n\r\n\x0c\nb\n\r\n\n";
+        let flags = ["-sE", "--normalize-blanks", "--formfeed-blank"];
+
+        let mut output = run_with_stdin(&flags, input);
+        assert_eq!(output.stdout, b"a$\n$\nb$\n^M$\n".to_vec());
+
+        output = run_with_stdin(
+            &[&flags[..], &["--squeeze-keep=last", "-n"]].concat(),
+            input,
+        );
+        assert_eq!(output.stdout, b"1 a$\n2 \x0c$\n3 b$\n4 $\n".to_vec());
+
+        // The checksum annotates the raw blank line that was kept.
+        let flags = ["-s", "--normalize-blanks", "--crc-lines"];
+        let first = run_with_stdin(&flags, b"\n\r\n");
+        output = run_with_stdin(&[&flags[..], &["--squeeze-keep=last"]].concat(), b"\n\r\n");
+        assert_eq!(first.stdout, b"00000000 \n".to_vec());
+        assert_eq!(output.stdout, b"acb39330 \r\n".to_vec());
+    }
+
+    // Test that --fifo-drain reads a non-blocking pipe to the end across a pause.
+    #[cfg(unix)]
+    #[test]
+    fn test_cat_fifo_drain() {
+        use std::os::unix::io::AsRawFd;
+
+        let run = |flags: &[&str]| {
+            let (reader, mut writer) = std::io::pipe().unwrap();
+            // SAFETY: fcntl only updates the status flags of the pipe we own.
+            unsafe {
+                let flags = libc::fcntl(reader.as_raw_fd(), libc::F_GETFL);
+                libc::fcntl(reader.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK);
+            }
+
+            let child = Command::cargo_bin("cat")
+                .unwrap()
+                .args(flags)
+                .stdin(reader)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap();
+
+            writer.write_all(b"a\nb").unwrap();
+            thread::sleep(std::time::Duration::from_millis(200));
+            // Without --fifo-drain, cat may be gone by now.
+            let _ = writer.write_all(b"c\n");
+            drop(writer);
+            child.wait_with_output().unwrap()
+        };
+
+        let output = run(&["--fifo-drain", "-n"]);
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"1 a\n2 bc\n".to_vec());
+
+        let output = run(&["-n"]);
+        assert!(!output.status.success());
+    }
+
+    // Test each --invalid rendering of bytes that are not valid UTF-8.
+    #[test]
+    fn test_cat_invalid() {
+        let input = b"caf\xc3\xa9 \xff\xfe ok \xe2\x82\n";
+
+        let mut output = run_with_stdin(&["--invalid=replace"], input);
+        assert_eq!(
+            output.stdout,
+            "café \u{FFFD}\u{FFFD} ok \u{FFFD}\n".as_bytes().to_vec()
+        );
+
+        output = run_with_stdin(&["--invalid=escape"], input);
+        assert_eq!(
+            output.stdout,
+            "café M-^?M-~ ok M-bM-^B\n".as_bytes().to_vec()
+        );
+
+        output = run_with_stdin(&["--invalid=marker", "-n"], input);
+        assert_eq!(
+            output.stdout,
+            "1 café <0xFF><0xFE> ok <0xE2><0x82>\n".as_bytes().to_vec()
+        );
+    }
+
+    // Test that a blank run is squeezed across input boundaries, including a repeated stdin.
+    #[test]
+    fn test_cat_squeeze_across_inputs() {
+        let dir = TempDir::new();
+        let first = &dir.file("first", b"a\n\n\n");
+        let second = &dir.file("second", b"\n\nb\n");
+
+        for (flags, expected_output) in [
+            ("-s", &b"a\n\nb\n"[..]),
+            ("-sn", b"1 a\n2 \n3 b\n"),
+            ("-sE", b"a$\n$\nb$\n"),
+        ] {
+            let output = run_with_stdin(&[flags, first, "-", second], b"\n");
+            assert_eq!(output.stdout, expected_output.to_vec(), "cat {}", flags);
+        }
+
+        // The second - reads nothing but must not restart the run.
+        let output = run_with_stdin(&["-s", "-", "-", second], b"a\n\n\n");
+        assert_eq!(output.stdout, b"a\n\nb\n".to_vec());
+    }
+
+    // Test that --assert-max-width passes narrow input and reports each line that is too wide.
+    #[test]
+    fn test_cat_assert_max_width() {
+        let mut output = run_with_stdin(&["--assert-max-width=5"], "short\nnaïve\n".as_bytes());
+        assert!(output.status.success());
+        assert_eq!(output.stderr, b"".to_vec());
+
+        output = run_with_stdin(&["--assert-max-width=5", "-n"], b"ab\nabcd\nabcdefg\n");
+        assert!(!output.status.success());
+        assert_eq!(output.stdout, b"1 ab\n2 abcd\n3 abcdefg\n".to_vec());
+        assert_eq!(
+            output.stderr,
+            b"cat: (standard input):2: line is 6 columns wide, more than 5\n\
+              cat: (standard input):3: line is 9 columns wide, more than 5\n"
+                .to_vec()
+        );
+
+        // Color escapes take no columns.
+        output = run_with_stdin(&["--assert-max-width=5"], b"\x1b[1mabcde\x1b[0m\n");
+        assert!(output.status.success());
+
+        output = run_with_stdin(&["--assert-max-width=5", "--raw"], b"a\n");
+        assert_eq!(output.status.code(), Some(2));
+    }
+
+    // Test that --split writes each input to its own numbered file.
+    #[test]
+    fn test_cat_split() {
+        let dir = TempDir::new();
+        let prefix = &dir.path("part");
+        let input = &dir.file("input", b"c\n\n\n");
+
+        let mut output = run_with_stdin(&["-ns", "--split", prefix, "-", input], b"a\nb\n");
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"".to_vec());
+        assert_eq!(
+            std::fs::read(format!("{}1", prefix)).unwrap(),
+            b"1 a\n2 b\n"
+        );
+        assert_eq!(std::fs::read(format!("{}2", prefix)).unwrap(), b"3 c\n4 \n");
+
+        output = run_with_stdin(
+            &["-n", "--number-per-file", "--split", prefix, "-", input],
+            b"a\nb\n",
+        );
+        assert!(output.status.success());
+        assert_eq!(
+            std::fs::read(format!("{}1", prefix)).unwrap(),
+            b"1 a\n2 b\n"
+        );
+        assert_eq!(
+            std::fs::read(format!("{}2", prefix)).unwrap(),
+            b"1 c\n2 \n3 \n"
+        );
+
+        // The files get the same --output-mode as standard output.
+        output = run_with_stdin(
+            &["--output-mode", "text", "--split", prefix, "-", input],
+            b"a\r\nb\n",
+        );
+        assert!(output.status.success());
+        assert_eq!(
+            std::fs::read(format!("{}1", prefix)).unwrap(),
+            b"a\r\nb\r\n"
+        );
+        assert_eq!(
+            std::fs::read(format!("{}2", prefix)).unwrap(),
+            b"c\r\n\r\n\r\n"
+        );
+    }
+
+    // Test that --number-style follows nl's -ba, -bt and -bn layouts.
+    #[test]
+    fn test_cat_number_style() {
+        let input = b"a\n\nb\n";
+
+        for (style, expected_output) in [
+            ("all", &b"     1\ta\n     2\t\n     3\tb\n"[..]),
+            ("nonblank", b"     1\ta\n       \n     2\tb\n"),
+            ("none", b"       a\n       \n       b\n"),
+        ] {
+            let output = run_with_stdin(&["--number-style", style], input);
+            assert_eq!(output.stdout, expected_output.to_vec(), "{}", style);
+        }
+
+        // Squeezed blanks are dropped before numbering.
+        let output = run_with_stdin(&["-s", "--number-style=nonblank"], b"a\n\n\n\nb\n");
+        assert_eq!(output.stdout, b"     1\ta\n       \n     2\tb\n".to_vec());
+    }
+
+    // Test that --input-buffering=none leaves unprocessed input for the next reader.
+    #[cfg(unix)]
+    #[test]
+    fn test_cat_input_buffering() {
+        let mut test_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_path.push("tests/test.txt");
+        let content = std::fs::read(&test_path).unwrap();
+        let cat = assert_cmd::cargo::cargo_bin("cat");
+
+        for (buffering, expected_output) in
+            [("none", content.clone()), ("full", content[..2].to_vec())]
+        {
+            // Both cats share the offset of the same open file.
+            let output = Command::new("sh")
+                .arg("-c")
+                .arg(format!(
+                    "\"$0\" -u --input-buffering={} --head-bytes=2; \"$0\"",
+                    buffering
+                ))
+                .arg(&cat)
+                .stdin(std::fs::File::open(&test_path).unwrap())
+                .output()
+                .unwrap();
+            assert_eq!(output.stdout, expected_output, "{}", buffering);
+        }
+
+        let output = run_with_stdin(&["--input-buffering=line", "-n"], b"a\nb\n");
+        assert_eq!(output.stdout, b"1 a\n2 b\n".to_vec());
+    }
+
+    // Test that --read-timeout on stdin with a small --input-buffering reads all the data that
+    // has arrived before timing out.
+    #[cfg(unix)]
+    #[test]
+    fn test_cat_read_timeout_input_buffering() {
+        let input = "line\n".repeat(1000);
+
+        for buffering in ["line", "none"] {
+            let mut child = Command::cargo_bin("cat")
+                .unwrap()
+                .args(["--read-timeout", "200", "--input-buffering", buffering])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap();
+
+            // Keep stdin open, so only the timeout can end the input.
+            let mut stdin = child.stdin.take().unwrap();
+            stdin.write_all(input.as_bytes()).unwrap();
+            let output = child.wait_with_output().unwrap();
+            drop(stdin);
+
+            assert_eq!(output.status.code(), Some(1), "{}", buffering);
+            assert_eq!(output.stdout, input.as_bytes(), "{}", buffering);
+            assert_eq!(output.stderr, b"cat: -: read timed out\n".to_vec());
+        }
+    }
+
+    // Test that --verbose notes once that -sn does not number the blank lines it drops.
+    #[test]
+    fn test_cat_squeeze_number_note() {
+        let input = b"a\n\n\n\nb\n\n\nc\n";
+
+        let mut output = run_with_stdin(&["-sn", "--verbose"], input);
+        assert_eq!(output.stdout, b"1 a\n2 \n3 b\n4 \n5 c\n".to_vec());
+        assert_eq!(
+            output.stderr,
+            b"cat: note: -s drops repeated blank lines before -n numbers them\n".to_vec()
+        );
+
+        output = run_with_stdin(&["-sn"], input);
+        assert_eq!(output.stderr, b"".to_vec());
+
+        output = run_with_stdin(&["-sn", "--verbose"], b"a\n\nb\n");
+        assert_eq!(output.stderr, b"".to_vec());
+    }
+
+    // Test ROT13 with --translate and the validation of --map-file tables.
+    #[test]
+    fn test_cat_translate() {
+        let rot13 = ["--translate", "a-zA-Z", "n-za-mN-ZA-M"];
+
+        let mut output = run_with_stdin(&[&rot13[..], &["-n"]].concat(), b"Hello, World!\n");
+        assert_eq!(output.stdout, b"1 Uryyb, Jbeyq!\n".to_vec());
+
+        // Applying ROT13 twice gives the input back.
+        output = run_with_stdin(&[&rot13[..], &rot13[..]].concat(), b"Hello\n");
+        assert_eq!(output.stdout, b"Hello\n".to_vec());
+
+        output = run_with_stdin(&["--translate", "\\t ", "_"], b"a b\tc\n");
+        assert_eq!(output.stdout, b"a_b_c\n".to_vec());
+
+        output = run_with_stdin(&["--translate", "z-a", "x"], b"a\n");
+        assert_eq!(output.status.code(), Some(2));
+
+        let dir = TempDir::new();
+        let path = dir.path("map");
+        let mut table: Vec<u8> = (0..=255).collect();
+        table[usize::from(b'a')] = b'@';
+        std::fs::write(&path, &table).unwrap();
+        output = run_with_stdin(&["--map-file", &path], b"banana\n");
+        assert_eq!(output.stdout, b"b@n@n@\n".to_vec());
+
+        std::fs::write(&path, &table[..255]).unwrap();
+        output = run_with_stdin(&["--map-file", &path], b"banana\n");
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(output.stdout, b"".to_vec());
+    }
+
+    // Test --since and --until over a log with a multiline record.
+    #[test]
+    fn test_cat_time_window() {
+        let log = b"header\n\
+            2024-03-01 09:59:59 boot\n\
+            2024-03-01 10:00:00 start\n\
+            2024-03-01 10:15:00 error\n  at main.rs:1\n\
+            2024-03-01 11:00:00 stop\n";
+
+        let mut output = run_with_stdin(
+            &[
+                "--since",
+                "2024-03-01 10:00:00",
+                "--until",
+                "2024-03-01 10:30:00",
+                "-n",
+            ],
+            log,
+        );
+        assert_eq!(
+            output.stdout,
+            b"3 2024-03-01 10:00:00 start\n\
+              4 2024-03-01 10:15:00 error\n\
+              5   at main.rs:1\n"
+                .to_vec()
+        );
+
+        output = run_with_stdin(
+            &["--since", "11:00", "--time-format", "%Y-%m-%d %H:%M"],
+            log,
+        );
+        assert_eq!(output.status.code(), Some(2));
+
+        output = run_with_stdin(
+            &[
+                "--since",
+                "[01/03/2024 10:30]",
+                "--time-format",
+                "[%d/%m/%Y %H:%M]",
+            ],
+            b"[01/03/2024 10:29] a\n[01/03/2024 10:31] b\n[02/03/2024 00:00] c\n",
+        );
+        assert_eq!(
+            output.stdout,
+            b"[01/03/2024 10:31] b\n[02/03/2024 00:00] c\n".to_vec()
+        );
+
+        output = run_with_stdin(&["--since", "2024-03-01 10:00:00", "--raw"], log);
+        assert_eq!(output.status.code(), Some(2));
+        output = run_with_stdin(&["--until", "2024-03-01 10:00:00", "--raw"], log);
+        assert_eq!(output.status.code(), Some(2));
+    }
+
+    // Test each --blank-lines-are kind on the same mix of blank-looking lines.
+    #[test]
+    fn test_cat_blank_lines_are() {
+        let input = b"a\n\n \t\n\r\n\x0c\n\nb\n";
+
+        for (kind, expected_output) in [
+            ("empty", &b"1 a\n\n2  \t\n3 \r\n4 \x0c\n\n5 b\n"[..]),
+            ("whitespace", b"1 a\n\n2 \r\n3 \x0c\n\n4 b\n"),
+            ("empty-or-crlf", b"1 a\n\n2  \t\n\r\n3 \x0c\n\n4 b\n"),
+            ("empty-or-formfeed", b"1 a\n\n2  \t\n3 \r\n\x0c\n4 b\n"),
+        ] {
+            let output = run_with_stdin(&["-sb", "--blank-lines-are", kind], input);
+            assert_eq!(output.stdout, expected_output.to_vec(), "{}", kind);
+        }
+    }
+
+    // Test that --preserve-blank-before-nonblank-only keeps one blank only before text.
+    #[test]
+    fn test_cat_preserve_blank_before_nonblank_only() {
+        let flag = "--preserve-blank-before-nonblank-only";
+
+        let mut output = run_with_stdin(&[flag, "-n"], b"\n\na\n\n\n\nb\n\n\n");
+        assert_eq!(output.stdout, b"1 \n2 a\n3 \n4 b\n".to_vec());
+
+        output = run_with_stdin(&[flag], b"a\n\nb\n");
+        assert_eq!(output.stdout, b"a\n\nb\n".to_vec());
+
+        output = run_with_stdin(&[flag, "-E"], b"a\n\n\r\n\n");
+        assert_eq!(output.stdout, b"a$\n$\n^M$\n".to_vec());
+
+        output = run_with_stdin(&[flag, "-", "-"], b"\n\n\n");
+        assert_eq!(output.stdout, b"".to_vec());
+
+        output = run_with_stdin(&[flag, "--raw"], b"a\n");
+        assert_eq!(output.status.code(), Some(2));
+    }
+
+    // Test that --split-marker unbundles a stream into the files named by its markers, and
+    // refuses to overwrite files or to write outside the current directory.
+    #[test]
+    fn test_cat_split_marker() {
+        let temp = TempDir::new();
+        let dir = temp.0.join("sections");
+        std::fs::create_dir(&dir).unwrap();
+        let split = |input: &[u8]| {
+            let mut cmd = Command::cargo_bin("cat").unwrap();
+            cmd.args(["-s", "--split-marker", "^--- FILE: (.+) ---$"])
+                .current_dir(&dir);
+            pipe_through(cmd, input)
+        };
+
+        let mut output = split(b"preamble\n--- FILE: a ---\na1\n\n\n\na2\n--- FILE: b ---\nb1\n");
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"preamble\n".to_vec());
+        assert_eq!(std::fs::read(dir.join("a")).unwrap(), b"a1\n\na2\n");
+        assert_eq!(std::fs::read(dir.join("b")).unwrap(), b"b1\n");
+
+        // An existing file is left alone.
+        output = split(b"--- FILE: a ---\nnew\n");
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(output.stderr, b"cat: -: a: File exists\n".to_vec());
+        assert_eq!(std::fs::read(dir.join("a")).unwrap(), b"a1\n\na2\n");
+
+        // Absolute paths, parent directories and any other directory are refused, as a
+        // symlinked directory could point outside.
+        let outside = temp.0.join("outside");
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&temp.0, dir.join("link")).unwrap();
+        for name in [outside.to_str().unwrap(), "..", "c/../../d", "link/x"] {
+            output = split(format!("--- FILE: {} ---\nx\n", name).as_bytes());
+            assert_eq!(output.status.code(), Some(1), "{}", name);
+            assert_eq!(
+                output.stderr,
+                format!(
+                    "cat: -: {}: section file name is not a plain file name\n",
+                    name
+                )
+                .into_bytes()
+            );
+        }
+        assert!(!outside.exists());
+
+        // Sections get the same --output-mode as standard output.
+        let mut cmd = Command::cargo_bin("cat").unwrap();
+        cmd.args([
+            "--split-marker",
+            "^--- FILE: (.+) ---$",
+            "--output-mode",
+            "text",
+        ])
+        .current_dir(&dir);
+        output = pipe_through(cmd, b"p\n--- FILE: c ---\nc1\nc2\n");
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"p\r\n".to_vec());
+        assert_eq!(std::fs::read(dir.join("c")).unwrap(), b"c1\r\nc2\r\n");
+
+        output = run_with_stdin(&["--split-marker", "^--- FILE: .+ ---$"], b"");
+        assert_eq!(output.status.code(), Some(2));
+
+        output = run_with_stdin(&["--split-marker", "^--- FILE: (.+) ---$", "--raw"], b"");
+        assert_eq!(output.status.code(), Some(2));
+    }
+
+    // Test that --nice is accepted and leaves the output alone.
+    #[test]
+    fn test_cat_nice() {
+        let output = run_with_stdin(&["--nice", "5", "-n"], b"a\n");
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"1 a\n".to_vec());
+        assert_eq!(output.stderr, b"".to_vec());
+
+        #[cfg(target_os = "linux")]
+        {
+            // The niceness is the 19th field of /proc/self/stat, after the command name.
+            let output = Command::cargo_bin("cat")
+                .unwrap()
+                .args(["--nice", "3", "/proc/self/stat"])
+                .output()
+                .unwrap();
+            let stat = String::from_utf8(output.stdout).unwrap();
+            let fields: Vec<&str> = stat.rsplit(") ").next().unwrap().split(' ').collect();
+            let parent = std::fs::read_to_string("/proc/self/stat").unwrap();
+            let parent: Vec<&str> = parent.rsplit(") ").next().unwrap().split(' ').collect();
+            let nice = |fields: &[&str]| fields[16].parse::<i32>().unwrap();
+            assert_eq!(nice(&fields), (nice(&parent) + 3).min(19));
+        }
+    }
+
+    // Test that --partial-line-timeout prints a partial line during a pause and the rest of it
+    // once the newline arrives.
+    #[cfg(unix)]
+    #[test]
+    fn test_cat_partial_line_timeout() {
+        use std::io::Read;
+        use std::sync::mpsc;
+
+        let mut child = Command::cargo_bin("cat")
+            .unwrap()
+            .args(["--partial-line-timeout", "50", "-n"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        let mut stdout = child.stdout.take().unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 64];
+            while let Ok(n) = stdout.read(&mut buf) {
+                if n == 0 || sender.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let read_for = |duration| {
+            let mut output = Vec::new();
+            while let Ok(chunk) = receiver.recv_timeout(duration) {
+                output.extend(chunk);
+            }
+            output
+        };
+
+        stdin.write_all(b"a\n50%").unwrap();
+        stdin.flush().unwrap();
+        let early = read_for(std::time::Duration::from_millis(1000));
+        assert_eq!(early, b"1 a\n2 50%".to_vec());
+
+        stdin.write_all(b" done\nb\n").unwrap();
+        drop(stdin);
+        assert!(child.wait().unwrap().success());
+        let rest = read_for(std::time::Duration::from_millis(1000));
+        assert_eq!(rest, b" done\n3 b\n".to_vec());
+    }
+
+    // Test that --validate-utf8 reports each invalid sequence with its offset and prints no
+    // content.
+    #[test]
+    fn test_cat_validate_utf8() {
+        let mut output = run_with_stdin(&["--validate-utf8", "-n"], "café ✓\n".as_bytes());
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"");
+
+        output = run_with_stdin(
+            &["--validate-utf8"],
+            b"ok\n\x80a\n\xc3\xa9\xc0\xaf\n\xe0\x80\x80 \xe2\x82\nz\xf0\x9f\x98",
+        );
+        assert!(!output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "(standard input):3: unexpected continuation byte (80)\n\
+             (standard input):8: byte never used in UTF-8 (c0)\n\
+             (standard input):9: unexpected continuation byte (af)\n\
+             (standard input):11: overlong, surrogate or out-of-range sequence (e0)\n\
+             (standard input):12: unexpected continuation byte (80)\n\
+             (standard input):13: unexpected continuation byte (80)\n\
+             (standard input):15: incomplete sequence (e2 82)\n\
+             (standard input):19: incomplete sequence (f0 9f 98)\n"
+        );
+    }
+
+    // Test that the line prefix, number, content, `$` and suffix always come in that order,
+    // with -s dropping whole lines first.
+    #[test]
+    fn test_cat_line_assembly_order() {
+        let input = b"a\n\n\nb";
+        let flags = ["--line-prefix=>", "--line-suffix=<", "-E", "-s"];
+
+        let mut output = run_with_stdin(&[&flags[..], &["-n"]].concat(), input);
+        assert_eq!(output.stdout, b">1 a$<\n>2 $<\n>3 b<".to_vec());
+
+        output = run_with_stdin(&[&flags[..], &["-b"]].concat(), input);
+        assert_eq!(output.stdout, b">1 a$<\n>$<\n>2 b<".to_vec());
+
+        output = run_with_stdin(&[&flags[..], &["--number-style=nonblank"]].concat(), input);
+        assert_eq!(
+            output.stdout,
+            b">     1\ta$<\n>       $<\n>     2\tb<".to_vec()
+        );
+    }
+
+    // Test that --tab-marker replaces ^I for -T and --show-tabs-aligned, and must not be empty.
+    #[test]
+    fn test_cat_tab_marker() {
+        let input = "a\tb\n\t\tc\n".as_bytes();
+
+        let mut output = run_with_stdin(&["-T", "--tab-marker=→"], input);
+        assert_eq!(output.stdout, "a→b\n→→c\n".as_bytes());
+
+        output = run_with_stdin(&["-A", "--tab-char=→"], input);
+        assert_eq!(output.stdout, "a→b$\n→→c$\n".as_bytes());
+
+        output = run_with_stdin(&["--show-tabs-aligned", "--tab-marker=→"], input);
+        assert_eq!(output.stdout, "a→      b\n→       →       c\n".as_bytes());
+
+        output = run_with_stdin(&["--show-tabs-aligned", "--tab-marker=<tab>"], input);
+        assert_eq!(output.stdout, "a<tab>  b\n<tab>   <tab>   c\n".as_bytes());
+
+        output = run_with_stdin(&["-T", "--tab-marker="], input);
+        assert_eq!(output.status.code(), Some(2));
+    }
+
+    // Test that --end-marker replaces the $ of -E, including after the ^M of a CRLF ending, and
+    // is not escaped by -v or -T.
+    #[test]
+    fn test_cat_end_marker() {
+        let input = b"a\tb\r\n\x01\n\nlast";
+
+        let mut output = run_with_stdin(&["-E", "--end-marker=<EOL>"], input);
+        assert_eq!(
+            output.stdout,
+            b"a\tb^M<EOL>\n\x01<EOL>\n<EOL>\nlast".to_vec()
+        );
+
+        output = run_with_stdin(&["-A", "--end-marker=<EOL>\t\u{23ce}"], input);
+        assert_eq!(
+            output.stdout,
+            "a^Ib^M<EOL>\t\u{23ce}\n^A<EOL>\t\u{23ce}\n<EOL>\t\u{23ce}\nlast".as_bytes()
+        );
+
+        output = run_with_stdin(&["-n", "-E", "--end-marker=", "--line-suffix=|"], input);
+        assert_eq!(output.stdout, b"1 a\tb^M|\n2 \x01|\n3 |\n4 last|".to_vec());
+    }
+
+    // Test that -s squeezes a run of millions of blank lines to one in bounded memory.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_cat_squeeze_long_blank_run() {
+        use std::os::unix::process::CommandExt;
+
+        const BLANKS: usize = 10_000_000;
+
+        let mut command = Command::cargo_bin("cat").unwrap();
+        command
+            .args(["-s", "-n"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
+        // SAFETY: setrlimit is async-signal-safe. Keeping the blanks would take far more than
+        // the 16 MiB heap allowed here.
+        unsafe {
+            command.pre_exec(|| {
+                let limit = libc::rlimit {
+                    rlim_cur: 16 << 20,
+                    rlim_max: 16 << 20,
+                };
+                if libc::setrlimit(libc::RLIMIT_DATA, &limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let mut child = command.spawn().unwrap();
+
+        let mut stdin = child.stdin.take().unwrap();
+        let writer = thread::spawn(move || {
+            stdin.write_all(b"a\n").unwrap();
+            let blanks = vec![b'\n'; 1 << 20];
+            for _ in 0..BLANKS / blanks.len() {
+                stdin.write_all(&blanks).unwrap();
+            }
+            stdin.write_all(b"b\n").unwrap();
+        });
+
+        let output = child.wait_with_output().unwrap();
+        writer.join().unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"1 a\n2 \n3 b\n".to_vec());
+    }
+
+    // Test that --output-mode=binary leaves bytes alone and text writes every bare LF as CRLF,
+    // also when the output is buffered or compressed.
+    #[test]
+    fn test_cat_output_mode() {
+        use super::to_crlf;
+
+        let input = b"a\nb\r\n\x00\xff\n\nend";
+        let text = b"a\r\nb\r\n\x00\xff\r\n\r\nend".to_vec();
+
+        let mut output = run_with_stdin(&["--output-mode=binary"], input);
+        assert_eq!(output.stdout, input.to_vec());
+
+        output = run_with_stdin(&["--output-mode=text"], input);
+        assert_eq!(output.stdout, text);
+
+        output = run_with_stdin(&["--output-mode=text", "-n", "-E"], b"a\n");
+        assert_eq!(output.stdout, b"1 a$\r\n".to_vec());
+
+        output = run_with_stdin(&["--output-mode=text", "--flush-interval=100"], input);
+        assert_eq!(output.stdout, text);
+
+        output = run_with_stdin(&["--output-mode=text", "--compress=gzip"], input);
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(output.stdout.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, text);
+
+        // A CR at the end of the previous write still counts.
+        assert_eq!(to_crlf(b"\nb\n", Some(b'\r')), b"\nb\r\n".to_vec());
+        assert_eq!(to_crlf(b"\n", None), b"\r\n".to_vec());
+    }
+
+    // Test that --squeeze-files limits -s to the chosen files, here the first of two.
+    #[test]
+    fn test_cat_squeeze_files() {
+        let dir = TempDir::new();
+        let first_string = dir.file("first", b"a\n\n\n\nb\n");
+        let second_string = dir.file("second", b"c\n\n\n\nd\n");
+        let files = [first_string.as_str(), second_string.as_str()];
+
+        let run = |which: &str| run_with_stdin(&[&["-s", which][..], &files].concat(), b"").stdout;
+        let squeezed_first = run("--squeeze-files=first");
+        let profile_first = run_with_stdin(
+            &[&["--profile=log", "--squeeze-files=first"][..], &files].concat(),
+            b"",
+        )
+        .stdout;
+        let squeezed_last = run("--squeeze-files=last");
+        let squeezed_second = run("--squeeze-files=2");
+        let squeezed_all = run("--squeeze-files=all");
+        let squeezed_none = run("--squeeze-files=3");
+
+        assert_eq!(squeezed_first, b"a\n\nb\nc\n\n\n\nd\n".to_vec());
+        // The log profile turns on -s too.
+        assert_eq!(profile_first, squeezed_first);
+        assert_eq!(squeezed_last, b"a\n\n\n\nb\nc\n\nd\n".to_vec());
+        assert_eq!(squeezed_second, squeezed_last);
+        assert_eq!(squeezed_all, b"a\n\nb\nc\n\nd\n".to_vec());
+        assert_eq!(squeezed_none, b"a\n\n\n\nb\nc\n\n\n\nd\n".to_vec());
+
+        let output = run_with_stdin(&["-s", "--squeeze-files=0,x"], b"");
+        assert_eq!(output.status.code(), Some(2));
+        let output = run_with_stdin(&["--squeeze-files=first"], b"");
+        assert_eq!(output.status.code(), Some(2));
+    }
+
+    // Test that --rate-limit paces the output to about the requested rate.
+    #[test]
+    fn test_cat_rate_limit() {
+        let input = vec![b'x'; 4096];
+
+        let start = std::time::Instant::now();
+        let output = run_with_stdin(&["--rate-limit=2K"], &input);
+        let elapsed = start.elapsed().as_secs_f64();
+        assert_eq!(output.stdout, input);
+        // 4 KiB at 2 KiB/s, less the tenth of a second the bucket starts with.
+        assert!((1.5..6.0).contains(&elapsed), "took {}s", elapsed);
+
+        let output = run_with_stdin(&["--rate-limit=0"], b"");
+        assert_eq!(output.status.code(), Some(2));
+        let output = run_with_stdin(&["--rate-limit=1X"], b"");
+        assert_eq!(output.status.code(), Some(2));
+    }
+
+    // Test that --classify tags blank, comment, text and binary lines ahead of the number.
+    #[test]
+    fn test_cat_classify() {
+        let input = b"# config\nkey = 1\n\n  // note\n; ini\n\x00\x01data\n\xff\n\tindented\n";
+
+        let mut output = run_with_stdin(&["--classify"], input);
+        assert_eq!(
+            output.stdout,
+            b"[comment] # config\n[text] key = 1\n[blank] \n[comment]   // note\n\
+              [comment] ; ini\n[binary] \x00\x01data\n[binary] \xff\n[text] \tindented\n"
+                .to_vec()
+        );
+
+        output = run_with_stdin(&["--classify", "-n", "--line-prefix=>"], b"a\n\n# b\n");
+        assert_eq!(
+            output.stdout,
+            b">[text] 1 a\n>[blank] 2 \n>[comment] 3 # b\n".to_vec()
+        );
+
+        // Tags come from the line as read, before -v changes it.
+        output = run_with_stdin(&["--classify", "-v"], b"\x01\n");
+        assert_eq!(output.stdout, b"[binary] ^A\n".to_vec());
+    }
+
+    // The GNU cat compared against by the property tests, looked up once.
+    fn shared_gnu_cat() -> Option<&'static PathBuf> {
+        static GNU_CAT: std::sync::OnceLock<Option<PathBuf>> = std::sync::OnceLock::new();
+        GNU_CAT.get_or_init(system_gnu_cat).as_ref()
+    }
+
+    // Input bytes weighted toward the line structure that squeezing and numbering look at.
+    fn fuzz_input() -> impl proptest::strategy::Strategy<Value = Vec<u8>> {
+        use proptest::prelude::*;
+
+        let byte = prop_oneof![
+            4 => Just(b'\n'),
+            1 => Just(b'\t'),
+            1 => Just(b'\r'),
+            1 => Just(b' '),
+            2 => Just(b'a'),
+            1 => any::<u8>(),
+        ];
+        proptest::collection::vec(byte, 0..96)
+    }
+
+    proptest::proptest! {
+        #![proptest_config(proptest::prelude::ProptestConfig::with_cases(128))]
+
+        // Test random inputs split over two files under random combinations of the GNU flags
+        // against GNU cat, shrinking any mismatch to a minimal case. Numbering uses GNU's layout
+        // through --number-format. Skipped when no GNU cat is installed.
+        #[test]
+        fn test_cat_gnu_property(
+            input in fuzz_input(),
+            split in proptest::prelude::any::<proptest::sample::Index>(),
+            flags in proptest::sample::subsequence(vec!["-n", "-b", "-s", "-E", "-T", "-v"], 0..=6),
+        ) {
+            let Some(system_cat) = shared_gnu_cat() else {
+                return Ok(());
+            };
+
+            let dir = TempDir::new();
+            let split = split.index(input.len() + 1);
+            let first = dir.file("first", &input[..split]);
+            let second = dir.file("second", &input[split..]);
+            let files = [first.as_str(), second.as_str()];
+
+            let mut ours_args = flags.clone();
+            if flags.contains(&"-n") || flags.contains(&"-b") {
+                ours_args.push("--number-format=%6d\\t");
+            }
+            ours_args.extend(files);
+            let ours = Command::cargo_bin("cat").unwrap().args(&ours_args).output().unwrap();
+            let theirs = Command::new(system_cat).args(&flags).args(files).output().unwrap();
+
+            proptest::prop_assert_eq!(ours.stdout, theirs.stdout);
+            proptest::prop_assert_eq!(ours.status.code(), theirs.status.code());
+        }
+    }
+
+    // Test that --diff-base prints only the changed, added and removed lines, and fails when
+    // there are any.
+    #[test]
+    fn test_cat_diff_base() {
+        let dir = TempDir::new();
+        let base_string = dir.file("base", b"host = a\nport = 80\nuser = root\nmode = 1\n");
+
+        let mut output = run_with_stdin(
+            &["--diff-base", &base_string, "-n"],
+            b"host = a\nport = 8080\nuser = root\n",
+        );
+        assert!(!output.status.success());
+        assert_eq!(
+            output.stdout,
+            b"- port = 80\n+ port = 8080\n- mode = 1\n".to_vec()
+        );
+
+        // The inputs are compared as one stream, and may run past the baseline.
+        output = run_with_stdin(
+            &["--diff-base", &base_string, "-", &base_string],
+            b"host = a\n",
+        );
+        assert_eq!(
+            output.stdout,
+            b"- port = 80\n+ host = a\n- user = root\n+ port = 80\n- mode = 1\n+ user = root\n\
+              + mode = 1\n"
+                .to_vec()
+        );
+
+        output = run_with_stdin(
+            &["--diff-base", &base_string],
+            b"host = a\nport = 80\nuser = root\nmode = 1",
+        );
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"".to_vec());
+
+        output = run_with_stdin(&["--diff-base", "/nonexistent/cat-test-base"], b"");
+        assert_eq!(output.status.code(), Some(1));
+    }
+
+    // Test that --add-final-newline ends the whole output with exactly one newline, and leaves
+    // empty output and output already ending with a newline alone.
+    #[test]
+    fn test_cat_add_final_newline() {
+        let mut output = run_with_stdin(&["--add-final-newline"], b"a\nb");
+        assert_eq!(output.stdout, b"a\nb\n".to_vec());
+
+        output = run_with_stdin(&["--add-final-newline"], b"a\nb\n");
+        assert_eq!(output.stdout, b"a\nb\n".to_vec());
+
+        output = run_with_stdin(&["--add-final-newline"], b"");
+        assert_eq!(output.stdout, b"".to_vec());
+
+        // Only the end of the overall output counts, not the end of each input.
+        output = run_with_stdin(&["--add-final-newline", "-", "-"], b"a");
+        assert_eq!(output.stdout, b"a\n".to_vec());
+
+        output = run_with_stdin(&["--add-final-newline", "-n", "-E"], b"x\ny");
+        assert_eq!(output.stdout, b"1 x$\n2 y\n".to_vec());
+
+        output = run_with_stdin(&["--add-final-newline", "--raw"], b"z");
+        assert_eq!(output.status.code(), Some(2));
+    }
+
+    // Test that --unique-field keeps the first of consecutive lines sharing a captured field.
+    #[test]
+    fn test_cat_unique_field() {
+        let input = b"[db] connect\n[db] retry\n[db] retry\n[web] start\n\n[db] ok\n[db] done\n";
+
+        let mut output = run_with_stdin(&["--unique-field", r"^\[(\w+)\]", "-n"], input);
+        assert_eq!(
+            output.stdout,
+            b"1 [db] connect\n2 [web] start\n3 \n4 [db] ok\n".to_vec()
+        );
+
+        // Without a capture group, the whole match is the field.
+        output = run_with_stdin(&["--unique-field", r"^\S+ \S+"], input);
+        assert_eq!(
+            output.stdout,
+            b"[db] connect\n[db] retry\n[web] start\n\n[db] ok\n[db] done\n".to_vec()
+        );
+
+        // Runs continue across inputs.
+        let dir = TempDir::new();
+        let next = dir.file("next", b"a3\nb\n");
+        output = run_with_stdin(&["--unique-field", "^(a)", "-", &next], b"a1\na2\n");
+        assert_eq!(output.stdout, b"a1\nb\n".to_vec());
+    }
+
+    // Test that --dual-number shows the input position beside the count of printed lines
+    // under each line filter.
+    #[test]
+    fn test_cat_dual_number() {
+        let input = b"a\nb\nc\nd\ne\nf\n";
+
+        let mut output = run_with_stdin(&["--dual-number", "--sample=2"], input);
+        assert_eq!(output.stdout, b"2:1 b\n4:2 d\n6:3 f\n".to_vec());
+
+        output = run_with_stdin(
+            &["--dual-number", "--unique-field=^(x)"],
+            b"x1\nx2\ny\nx3\n",
+        );
+        assert_eq!(output.stdout, b"1:1 x1\n3:2 y\n4:3 x3\n".to_vec());
+
+        output = run_with_stdin(
+            &["--dual-number", "-s", "--start-number=10"],
+            b"a\n\n\n\nb\n",
+        );
+        assert_eq!(output.stdout, b"1:10 a\n2:11 \n5:12 b\n".to_vec());
+
+        // Without a filter, both numbers agree.
+        output = run_with_stdin(&["--dual-number"], b"a\nb\n");
+        assert_eq!(output.stdout, b"1:1 a\n2:2 b\n".to_vec());
+
+        output = run_with_stdin(&["--dual-number", "-b"], input);
+        assert_eq!(output.status.code(), Some(2));
+    }
+
+    // Test that --stdin-timeout gives up on a terminal that sends nothing, and leaves a pipe
+    // alone.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_cat_stdin_timeout() {
+        // SAFETY: the calls set up a new pseudoterminal and only touch its descriptors.
+        let terminal = unsafe {
+            let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(master >= 0);
+            assert_eq!(libc::grantpt(master), 0);
+            assert_eq!(libc::unlockpt(master), 0);
+            let name = std::ffi::CStr::from_ptr(libc::ptsname(master)).to_owned();
+            let terminal = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(name.to_str().unwrap())
+                .unwrap();
+            (master, terminal)
+        };
+        let (master, terminal) = terminal;
+
+        let mut child = Command::cargo_bin("cat")
+            .unwrap()
+            .args(["--stdin-timeout", "200", "-n"])
+            .stdin(terminal)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // Never let a hanging cat block the suite.
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while child.try_wait().unwrap().is_none() && std::time::Instant::now() < deadline {
+            thread::sleep(std::time::Duration::from_millis(20));
+        }
+        let _ = child.kill();
+        let output = child.wait_with_output().unwrap();
+        // SAFETY: the master descriptor is ours and no longer used.
+        unsafe { libc::close(master) };
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(output.stderr, b"cat: -: read timed out\n".to_vec());
+
+        // A pipe is not a terminal, so a slow writer is waited for.
+        let mut child = Command::cargo_bin("cat")
+            .unwrap()
+            .args(["--stdin-timeout", "50"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        thread::sleep(std::time::Duration::from_millis(300));
+        stdin.write_all(b"late\n").unwrap();
+        drop(stdin);
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"late\n".to_vec());
+    }
+
+    // Test the --summary counters for a run over two files, one of them missing, and stdin.
+    #[test]
+    fn test_cat_summary() {
+        let dir = TempDir::new();
+        let first_string = dir.file("first", b"a\n\n\n\nb\n");
+        let second_string = dir.file("second", b"\n\nc");
+
+        let files = [
+            first_string.as_str(),
+            "/nonexistent/cat-test",
+            "-",
+            &second_string,
+        ];
+        let mut output = run_with_stdin(&[&["-s", "--summary"][..], &files].concat(), b"d\n\n");
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(output.stdout, b"a\n\nb\nd\n\nc".to_vec());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(
+            stderr.lines().last(),
+            Some("cat: summary: bytes=13 lines=10 files=3 failed=1 squeezed=4")
+        );
+
+        // The formatting path counts the same.
+        output = run_with_stdin(&[&["-sn", "--summary"][..], &files].concat(), b"d\n\n");
+        assert_eq!(output.stdout, b"1 a\n2 \n3 b\n4 d\n5 \n6 c".to_vec());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(
+            stderr.lines().last(),
+            Some("cat: summary: bytes=13 lines=10 files=3 failed=1 squeezed=4")
+        );
+
+        // A file skipped by --on-missing=skip is not counted.
+        output = run_with_stdin(
+            &[
+                "--summary",
+                "--on-missing=skip",
+                "/nonexistent/cat-test",
+                "-",
+            ],
+            b"a\n",
+        );
+        assert!(output.status.success());
+        assert_eq!(
+            output.stderr,
+            b"cat: summary: bytes=2 lines=1 files=1 failed=0 squeezed=0\n".to_vec()
+        );
     }
 }